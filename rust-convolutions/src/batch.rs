@@ -0,0 +1,203 @@
+//! Running one operation over many images
+
+use crate::image_io;
+use crate::prelude::*;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// default output name, placed beside the input
+pub const DEFAULT_TEMPLATE: &str = "{stem}-{kernel}.{ext}";
+
+/// the outcome of processing one file
+pub struct FileReport {
+    pub input: PathBuf,
+    pub output: Result<PathBuf>,
+    pub elapsed: Duration,
+}
+
+/// Expand a directory into the images directly inside it, or a glob
+/// pattern such as `input_images/*.jpg` into the matching files. The
+/// paths are sorted so runs are reproducible.
+pub fn collect_inputs(input: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(input);
+    let mut paths = if path.is_dir() {
+        std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?
+            .into_iter()
+            .filter(|p| p.is_file() && image_io::is_image_path(p))
+            .collect::<Vec<PathBuf>>()
+    } else {
+        glob::glob(input)
+            .map_err(|e| Error::Generic(format!("bad pattern '{}': {}", input, e)))?
+            .filter_map(|entry| entry.ok())
+            .filter(|p| p.is_file() && image_io::is_image_path(p))
+            .collect::<Vec<PathBuf>>()
+    };
+
+    if paths.is_empty() {
+        return Err(Error::Generic(format!("no images match '{}'", input)));
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// Fill an output name template for `input`. `{stem}` and `{ext}` come
+/// from the input file name and `{kernel}` is the operation label. The
+/// result goes in `out_dir`, or beside the input when it is `None`.
+pub fn output_name(template: &str, input: &Path, label: &str, out_dir: Option<&Path>) -> PathBuf {
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = input
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "png".to_string());
+
+    let name = template
+        .replace("{stem}", &stem)
+        .replace("{ext}", &ext)
+        .replace("{kernel}", label);
+
+    match out_dir {
+        Some(dir) => dir.join(name),
+        None => input.with_file_name(name),
+    }
+}
+
+/// Pair each input with its output name. Inputs that are the output of
+/// another input, such as the results of an earlier run beside the
+/// inputs, are skipped. Two inputs writing to the same output, as with a
+/// template without `{stem}`, is an error.
+pub fn plan_outputs(
+    inputs: &[PathBuf],
+    template: &str,
+    label: &str,
+    out_dir: Option<&Path>,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let name = |input: &PathBuf| output_name(template, input, label, out_dir);
+    let produced = inputs.iter().map(name).collect::<HashSet<PathBuf>>();
+
+    let mut seen: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    let mut plan = vec![];
+    for input in inputs.iter().filter(|p| !produced.contains(*p)) {
+        let output = name(input);
+        if let Some(other) = seen.insert(output.clone(), input) {
+            return Err(Error::Generic(format!(
+                "'{}' and '{}' would both be written to '{}', add {{stem}} to the template",
+                other.display(),
+                input.display(),
+                output.display()
+            )));
+        }
+        plan.push((input.to_owned(), output));
+    }
+
+    if plan.is_empty() {
+        return Err(Error::Generic(
+            "every input would be overwritten by an output".to_string(),
+        ));
+    }
+    Ok(plan)
+}
+
+/// Run `process` over every input, on `jobs` threads (1 runs in order on
+/// the current thread, 0 uses every core). `plan` pairs each input with
+/// the output `process` writes to, see `plan_outputs`. Failures are
+/// recorded in the reports rather than stopping the batch.
+pub fn run_batch<F>(plan: &[(PathBuf, PathBuf)], jobs: usize, process: F) -> Result<Vec<FileReport>>
+where
+    F: Fn(&Path, &Path) -> Result<()> + Sync,
+{
+    let run_one = |(input, output): &(PathBuf, PathBuf)| {
+        let now = Instant::now();
+        let output = process(input, output).map(|_| output.to_owned());
+        FileReport {
+            input: input.to_owned(),
+            output,
+            elapsed: now.elapsed(),
+        }
+    };
+
+    if jobs == 1 {
+        return Ok(plan.iter().map(run_one).collect());
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| Error::Generic(format!("could not start threads: {}", e)))?;
+    Ok(pool.install(|| plan.par_iter().map(run_one).collect()))
+}
+
+/// Print a line per file and the totals, returning an error if any file
+/// failed so the process exits non-zero.
+pub fn print_summary(reports: &[FileReport], wall: Duration) -> Result<()> {
+    let mut failed = 0;
+    for report in reports {
+        match &report.output {
+            Ok(output) => println!(
+                "ok   {} -> {} ({:.3} sec)",
+                report.input.display(),
+                output.display(),
+                report.elapsed.as_secs_f32()
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("fail {}: {}", report.input.display(), e);
+            }
+        }
+    }
+
+    let busy: Duration = reports.iter().map(|r| r.elapsed).sum();
+    println!(
+        "{} files, {} ok, {} failed in {:.3} sec ({:.3} sec of processing)",
+        reports.len(),
+        reports.len() - failed,
+        failed,
+        wall.as_secs_f32(),
+        busy.as_secs_f32()
+    );
+
+    if failed > 0 {
+        return Err(Error::Generic(format!(
+            "{} of {} files failed",
+            failed,
+            reports.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn plan_skips_outputs_of_an_earlier_run() {
+        let inputs = paths(&["in/a.png", "in/a-box.png", "in/b.png", "in/b-box.png"]);
+        let plan = plan_outputs(&inputs, DEFAULT_TEMPLATE, "box", None).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                (PathBuf::from("in/a.png"), PathBuf::from("in/a-box.png")),
+                (PathBuf::from("in/b.png"), PathBuf::from("in/b-box.png")),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_rejects_colliding_outputs() {
+        let inputs = paths(&["in/a.png", "in/b.png"]);
+        assert!(plan_outputs(&inputs, "{kernel}.png", "box", Some(Path::new("out"))).is_err());
+        assert!(plan_outputs(&inputs, "{stem}.png", "box", Some(Path::new("out"))).is_ok());
+    }
+}
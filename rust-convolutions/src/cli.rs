@@ -0,0 +1,835 @@
+//! Command line interface
+
+use crate::batch;
+use crate::color::{Alpha, ColorOptions, Grayscale};
+use crate::conv1d::{self, Method, OutputMode};
+use crate::convolve;
+use crate::deconvolve;
+use crate::dft::Backend;
+use crate::fir;
+use crate::frequency::{FrequencyFilter, Passband, Response};
+use crate::image_io::{read_image_alpha, save_img, BitDepth};
+use crate::kernel_file;
+use crate::mapping::ValueMap;
+use crate::padding::Padding;
+use crate::pipeline::{CompiledPipeline, Pipeline};
+use crate::pooling::PoolMethod;
+use crate::prelude::*;
+use crate::registry::{self, KernelSpec};
+use crate::signal::{self, SampleFormat, SignalKernel};
+use crate::spectrum;
+use crate::stft::{self, StftConfig};
+use crate::streaming::BlockMethod;
+use crate::volume::{self, Volume, VolumeKernel};
+use crate::windowing::{Correction, WindowFunction};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(about = "Convolutions, pooling and fourier transforms on images")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Filter an image with a kernel in the spatial domain
+    Convolve {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: ConvolveArgs,
+    },
+    /// Pool an image with a stride of 1, keeping its size
+    Pool {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: PoolArgs,
+    },
+    /// Convolve an image with a kernel through the fft
+    Fft {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: FftArgs,
+    },
+    /// Filter an image with a mask over its spectrum
+    Filter {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: FilterArgs,
+    },
+    /// Undo a known blur of an image
+    Deconvolve {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: DeconvolveArgs,
+    },
+    /// Save the centred log-magnitude or phase spectrum of an image
+    Spectrum {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: SpectrumArgs,
+    },
+    /// Run a pipeline file of chained steps on an image
+    Pipeline {
+        #[command(flatten)]
+        io: ImageArgs,
+        #[command(flatten)]
+        args: PipelineArgs,
+    },
+    /// Filter a 1d signal or WAV file with a 1d kernel
+    Signal(SignalArgs),
+    /// Save the spectrogram of a 1d signal as an image
+    Spectrogram(SpectrogramArgs),
+    /// Print the taps and frequency response of a 1d kernel
+    Response(ResponseArgs),
+    /// Filter or pool a stack of image slices as one 3d volume
+    Volume(VolumeArgs),
+    /// Run one operation over a directory or glob of images
+    Batch {
+        #[command(flatten)]
+        args: BatchArgs,
+        #[command(subcommand)]
+        operation: Operation,
+    },
+    /// Inspect and save the named kernels
+    Kernel {
+        #[command(subcommand)]
+        command: KernelCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KernelCommand {
+    /// List the available kernels and their parameters
+    List,
+    /// Print the values of a kernel spec
+    Show { spec: KernelSpec },
+    /// Write a kernel spec to a text, csv or json file, picked by the
+    /// extension, so it can be edited and read back with file:<path>
+    Save { spec: KernelSpec, output: PathBuf },
+}
+
+/// An operation that turns one image into another, run on its own or by
+/// `batch`.
+#[derive(Subcommand)]
+pub enum Operation {
+    /// Filter with a kernel in the spatial domain
+    Convolve(ConvolveArgs),
+    /// Pool with a stride of 1
+    Pool(PoolArgs),
+    /// Convolve through the fft
+    Fft(FftArgs),
+    /// Filter with a mask over the spectrum
+    Filter(FilterArgs),
+    /// Undo a known blur
+    Deconvolve(DeconvolveArgs),
+    /// Centred log-magnitude or phase spectrum
+    Spectrum(SpectrumArgs),
+    /// Run a pipeline file
+    Pipeline(PipelineArgs),
+}
+
+#[derive(Args)]
+pub struct ImageArgs {
+    /// image to read
+    pub input: PathBuf,
+    /// where to write the result, defaults to `<stem>-<operation>.<ext>`
+    /// next to the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub format: FormatArgs,
+}
+
+#[derive(Args)]
+pub struct FormatArgs {
+    /// comma separated mappings applied before saving: clamp, minmax, abs,
+    /// offset, percentile:<low>:<high>, gamma:<gamma>
+    #[arg(long, value_delimiter = ',')]
+    pub map: Vec<ValueMap>,
+    /// 8, 16 (png, tif) or float (tif, exr, pfm), defaults to float for
+    /// .exr and .pfm and 8 otherwise. Integer depths clip to 0-1.
+    #[arg(long)]
+    pub depth: Option<BitDepth>,
+    /// grey conversion on load: bt601, bt709, average, red, green, blue,
+    /// or linear, which decodes sRGB and encodes it again on save
+    #[arg(long, default_value = "bt601")]
+    pub gray: Grayscale,
+    /// alpha handling: drop, premultiply or composite:<background>.
+    /// premultiply divides by the alpha again on save and writes it back
+    #[arg(long, default_value = "drop")]
+    pub alpha: Alpha,
+}
+
+impl FormatArgs {
+    fn color(&self) -> ColorOptions {
+        ColorOptions {
+            gray: self.gray,
+            alpha: self.alpha,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BatchArgs {
+    /// directory of images, or a glob such as 'input_images/*.jpg'
+    pub input: String,
+    /// directory for the results, defaults to beside each input
+    #[arg(long)]
+    pub out_dir: Option<PathBuf>,
+    /// output file name with {stem}, {ext} and {kernel} placeholders
+    #[arg(long, default_value = batch::DEFAULT_TEMPLATE)]
+    pub template: String,
+    /// number of images processed at once, 0 uses every core
+    #[arg(short, long, default_value_t = 1)]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub format: FormatArgs,
+}
+
+#[derive(Args)]
+pub struct ConvolveArgs {
+    /// kernel spec, such as gaussian:31:5 or file:kernels/blur.csv
+    #[arg(short, long)]
+    pub kernel: KernelSpec,
+    #[arg(short, long, value_enum, default_value_t = Algorithm::Direct)]
+    pub algorithm: Algorithm,
+    /// zero, reflection or constant:<value>
+    #[arg(short, long, default_value = "reflection")]
+    pub padding: Padding,
+    /// cross-correlate instead of convolving, the kernel is not flipped
+    #[arg(long)]
+    pub correlate: bool,
+}
+
+#[derive(Args)]
+pub struct PoolArgs {
+    /// max, min, avg, l2 or stochastic
+    #[arg(short, long, default_value = "max")]
+    pub method: PoolMethod,
+    /// side length of the pooling window
+    #[arg(short, long, default_value_t = 3)]
+    pub size: usize,
+}
+
+#[derive(Args)]
+pub struct FftArgs {
+    #[arg(short, long)]
+    pub kernel: KernelSpec,
+    /// rustfft, or handwritten for the recursive fft in the dft module
+    #[arg(short, long, default_value = "rustfft")]
+    pub backend: Backend,
+}
+
+#[derive(Args)]
+pub struct FilterArgs {
+    /// lowpass:<cutoff>, highpass:<cutoff>, bandpass:<low>:<high> or
+    /// notch:<u>:<v>:<radius>, in cycles per pixel up to 0.5
+    #[arg(long)]
+    pub band: Passband,
+    /// ideal, gaussian or butterworth:<order>
+    #[arg(short, long, default_value = "gaussian")]
+    pub response: Response,
+    /// zero, reflection or constant:<value>
+    #[arg(short, long, default_value = "reflection")]
+    pub padding: Padding,
+}
+
+#[derive(Args)]
+pub struct DeconvolveArgs {
+    /// kernel spec of the blur, such as gaussian:15:2
+    #[arg(short, long)]
+    pub kernel: KernelSpec,
+    #[arg(short, long, value_enum, default_value_t = Deconvolution::Wiener)]
+    pub method: Deconvolution,
+    /// noise to signal power ratio for wiener
+    #[arg(long, default_value_t = 0.01)]
+    pub nsr: f64,
+    /// iterations of richardson-lucy
+    #[arg(long, default_value_t = 30)]
+    pub iterations: usize,
+    /// zero, reflection or constant:<value>
+    #[arg(short, long, default_value = "reflection")]
+    pub padding: Padding,
+}
+
+#[derive(Args)]
+pub struct SpectrumArgs {
+    /// rustfft, or handwritten for the recursive fft, which pads each side
+    /// to a power of 2
+    #[arg(short, long, default_value = "rustfft")]
+    pub backend: Backend,
+    /// save the phase instead of the log-magnitude
+    #[arg(long)]
+    pub phase: bool,
+    /// window applied before transforming to reduce leakage: rectangular,
+    /// hann, hamming, blackman, kaiser:<beta> or tukey:<alpha>
+    #[arg(short, long, default_value = "rectangular")]
+    pub window: WindowFunction,
+    /// rescale the windowed image so peak heights (amplitude) or noise
+    /// power (energy) match the unwindowed spectrum, or none
+    #[arg(long, default_value = "amplitude")]
+    pub correction: Correction,
+}
+
+#[derive(Args)]
+pub struct SignalArgs {
+    /// text file of samples or WAV file
+    pub input: PathBuf,
+    /// where to write the result, defaults to `<stem>-<kernel>.<ext>` next
+    /// to the input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// file:<path> of a text or WAV kernel, such as an impulse response,
+    /// or a designed filter: lowpass:<taps>:<cutoff>,
+    /// highpass:<taps>:<cutoff>, bandpass:<taps>:<low>:<high>,
+    /// bandstop:<taps>:<low>:<high>, average:<n> or
+    /// derivative:<order>:<points>. Cutoffs are in cycles per sample, up
+    /// to 0.5. A kernel with one channel filters every channel of the
+    /// input.
+    #[arg(short, long)]
+    pub kernel: SignalKernel,
+    /// full, same or valid
+    #[arg(long, default_value = "same")]
+    pub mode: OutputMode,
+    /// direct, fft, overlap-add, overlap-save or auto
+    #[arg(short, long, default_value = "auto")]
+    pub method: Method,
+    /// zero, reflection or constant:<value>
+    #[arg(short, long, default_value = "zero")]
+    pub padding: Padding,
+    /// multiply the result by this
+    #[arg(long, default_value_t = 1.)]
+    pub gain: f64,
+    /// WAV sample format: i8, i16, i24, i32 or f32, defaults to the input's
+    #[arg(long)]
+    pub sample_format: Option<SampleFormat>,
+    /// filter a WAV into a WAV in chunks without loading either, for
+    /// signals too long for memory. Uses overlap-save when that is the
+    /// method and overlap-add otherwise, with zero padding
+    #[arg(long)]
+    pub stream: bool,
+}
+
+#[derive(Args)]
+pub struct SpectrogramArgs {
+    /// text file of samples or WAV file, channels are averaged
+    pub input: PathBuf,
+    /// image to write, defaults to `<stem>-spectrogram.png` next to the
+    /// input
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// rectangular, hann, hamming, blackman, kaiser:<beta> or tukey:<alpha>
+    #[arg(short, long, default_value = "hann")]
+    pub window: WindowFunction,
+    /// samples in each frame
+    #[arg(long, default_value_t = 256)]
+    pub window_size: usize,
+    /// samples between frames, defaults to a quarter window
+    #[arg(long)]
+    pub hop: Option<usize>,
+    /// fft length, defaults to the window size
+    #[arg(long)]
+    pub fft_size: Option<usize>,
+    /// decibels below the loudest value shown, quieter values are black
+    #[arg(long, default_value_t = 80.)]
+    pub range: f64,
+}
+
+#[derive(Args)]
+pub struct ResponseArgs {
+    /// a 1d kernel as for `signal`, such as lowpass:31:0.1
+    pub kernel: SignalKernel,
+    /// frequencies listed between 0 and 0.5 cycles per sample, plus one
+    #[arg(short, long, default_value_t = 16)]
+    pub points: usize,
+}
+
+#[derive(Args)]
+pub struct VolumeArgs {
+    /// directory of slices, or a glob such as 'ct/*.png', stacked in name
+    /// order
+    pub input: String,
+    /// directory for the result slices
+    #[arg(long)]
+    pub out_dir: PathBuf,
+    /// output file name with {stem}, {ext} and {kernel} placeholders
+    #[arg(long, default_value = batch::DEFAULT_TEMPLATE)]
+    pub template: String,
+    /// 3d kernel: gaussian:<size>:<sigma> or box:<size>
+    #[arg(short, long, required_unless_present = "pool", conflicts_with = "pool")]
+    pub kernel: Option<VolumeKernel>,
+    /// pool instead of filtering: max, min, avg, l2 or stochastic
+    #[arg(long)]
+    pub pool: Option<PoolMethod>,
+    /// side length of the pooling cube
+    #[arg(short, long, default_value_t = 3)]
+    pub size: usize,
+    #[arg(short, long, value_enum, default_value_t = Algorithm::Direct)]
+    pub algorithm: Algorithm,
+    /// zero, reflection or constant:<value>
+    #[arg(short, long, default_value = "reflection")]
+    pub padding: Padding,
+    /// cross-correlate instead of convolving, the kernel is not flipped
+    #[arg(long)]
+    pub correlate: bool,
+    #[command(flatten)]
+    pub format: FormatArgs,
+}
+
+#[derive(Args)]
+pub struct PipelineArgs {
+    /// pipeline description, .toml or .json, compiled once while the
+    /// arguments are parsed
+    #[arg(short, long = "file", value_name = "FILE", value_parser = compile_pipeline)]
+    pub pipeline: CompiledPipeline,
+}
+
+fn compile_pipeline(path: &str) -> std::result::Result<CompiledPipeline, String> {
+    Pipeline::load(path)
+        .and_then(|p| p.compile())
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Algorithm {
+    Direct,
+    Fft,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Deconvolution {
+    /// one step in the frequency domain
+    Wiener,
+    /// iterative, keeps the image non-negative
+    RichardsonLucy,
+}
+
+pub fn run(cli: Cli) -> Result<()> {
+    let now = Instant::now();
+
+    let (io, operation) = match cli.command {
+        Command::Convolve { io, args } => (io, Operation::Convolve(args)),
+        Command::Pool { io, args } => (io, Operation::Pool(args)),
+        Command::Fft { io, args } => (io, Operation::Fft(args)),
+        Command::Filter { io, args } => (io, Operation::Filter(args)),
+        Command::Deconvolve { io, args } => (io, Operation::Deconvolve(args)),
+        Command::Spectrum { io, args } => (io, Operation::Spectrum(args)),
+        Command::Pipeline { io, args } => (io, Operation::Pipeline(args)),
+        Command::Batch { args, operation } => return run_batch(&args, &operation),
+        Command::Kernel { command } => return run_kernel(command),
+        Command::Signal(args) => return run_signal(&args),
+        Command::Spectrogram(args) => return run_spectrogram(&args),
+        Command::Response(args) => return run_response(&args),
+        Command::Volume(args) => return run_volume(&args),
+    };
+
+    operation.validate()?;
+    let output = match io.output {
+        Some(output) => output,
+        None => batch::output_name(batch::DEFAULT_TEMPLATE, &io.input, &operation.label(), None),
+    };
+    operation.process(&io.input, &output, &io.format)?;
+    println!(
+        "{} {}: {:.3?} sec",
+        operation.name(),
+        operation.label(),
+        now.elapsed().as_secs_f32()
+    );
+
+    Ok(())
+}
+
+fn run_batch(args: &BatchArgs, operation: &Operation) -> Result<()> {
+    let now = Instant::now();
+    operation.validate()?;
+
+    let inputs = batch::collect_inputs(&args.input)?;
+    if let Some(dir) = &args.out_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let plan = batch::plan_outputs(
+        &inputs,
+        &args.template,
+        &operation.label(),
+        args.out_dir.as_deref(),
+    )?;
+    let reports = batch::run_batch(&plan, args.jobs, |input, output| {
+        operation.process(input, output, &args.format)
+    })?;
+
+    batch::print_summary(&reports, now.elapsed())
+}
+
+fn run_signal(args: &SignalArgs) -> Result<()> {
+    let now = Instant::now();
+
+    let kernel = args.kernel.load()?;
+    args.kernel.check_sample_rate(&kernel, &args.input)?;
+    let output = match &args.output {
+        Some(output) => output.to_owned(),
+        None => batch::output_name(
+            batch::DEFAULT_TEMPLATE,
+            &args.input,
+            &args.kernel.label(),
+            None,
+        ),
+    };
+
+    if args.stream {
+        if !signal::is_wav(&args.input) || !signal::is_wav(&output) {
+            return Err(Error::Generic(
+                "--stream reads and writes .wav files".to_string(),
+            ));
+        }
+        if args.padding != Padding::Zero {
+            return Err(Error::Generic(
+                "--stream only supports zero padding".to_string(),
+            ));
+        }
+        let method = match args.method {
+            Method::OverlapSave => BlockMethod::OverlapSave,
+            _ => BlockMethod::OverlapAdd,
+        };
+        let (channels, samples) = signal::stream_wav(
+            &args.input,
+            &output,
+            &kernel,
+            args.mode,
+            method,
+            args.gain,
+            args.sample_format,
+        )?;
+        println!(
+            "Signal {}: {} channels of {} samples streamed in {:.3?} sec",
+            args.kernel.label(),
+            channels,
+            samples,
+            now.elapsed().as_secs_f32()
+        );
+        return Ok(());
+    }
+
+    let mut audio = signal::read_audio(&args.input)?;
+
+    // a kernel per channel when the counts match, otherwise one for all
+    let mono = kernel.mixdown();
+    audio.channels = audio
+        .channels
+        .iter()
+        .enumerate()
+        .map(|(c, x)| {
+            let k = if kernel.channels.len() == audio.channels.len() {
+                &kernel.channels[c]
+            } else {
+                &mono
+            };
+            conv1d::convolve(x, k, args.mode, args.padding, args.method)
+                .into_iter()
+                .map(|v| v * args.gain)
+                .collect()
+        })
+        .collect();
+    if let Some(format) = args.sample_format {
+        audio.format = format;
+    }
+
+    signal::write_audio(&output, &audio)?;
+
+    println!(
+        "Signal {}: {} channels of {} samples in {:.3?} sec",
+        args.kernel.label(),
+        audio.channels.len(),
+        audio.len(),
+        now.elapsed().as_secs_f32()
+    );
+    Ok(())
+}
+
+fn run_spectrogram(args: &SpectrogramArgs) -> Result<()> {
+    let now = Instant::now();
+
+    let mut config = StftConfig::new(args.window_size);
+    config.window = args.window;
+    config.hop = args.hop.unwrap_or(config.hop);
+    config.fft_size = args.fft_size.unwrap_or(config.fft_size);
+    config.validate()?;
+    if args.range <= 0. {
+        return Err(Error::Generic("range must be positive".to_string()));
+    }
+
+    let samples = signal::read_audio(&args.input)?.mixdown();
+    let img = stft::spectrogram(&stft::stft(&samples, &config), args.range);
+
+    let output = match &args.output {
+        Some(output) => output.to_owned(),
+        None => batch::output_name("{stem}-{kernel}.png", &args.input, "spectrogram", None),
+    };
+    let (width, height) = (img[0].len() as u32, img.len() as u32);
+    let depth = BitDepth::for_path(&output);
+    save_img(&output, &img, &[], depth, &ColorOptions::default(), None)?;
+
+    println!(
+        "Spectrogram {}x{}: {:.3?} sec",
+        width,
+        height,
+        now.elapsed().as_secs_f32()
+    );
+    Ok(())
+}
+
+fn run_response(args: &ResponseArgs) -> Result<()> {
+    let kernel = args.kernel.load()?.mixdown();
+    if args.points == 0 {
+        return Err(Error::Generic("points must be at least 1".to_string()));
+    }
+
+    println!("{} ({} taps)", args.kernel.label(), kernel.len());
+    for tap in &kernel {
+        println!("{:>10.6}", tap);
+    }
+
+    let response = fir::frequency_response(&kernel, args.points);
+    let db = fir::magnitude_db(&response);
+    println!("\n{:>10} {:>10} {:>10}", "frequency", "gain dB", "phase");
+    for (i, (h, g)) in response.iter().zip(db.iter()).enumerate() {
+        let f = i as f64 / (2 * args.points) as f64;
+        println!("{:>10.4} {:>10.2} {:>10.4}", f, g, h.arg());
+    }
+
+    Ok(())
+}
+
+fn run_volume(args: &VolumeArgs) -> Result<()> {
+    let now = Instant::now();
+
+    let label = match (&args.kernel, args.pool) {
+        (Some(kernel), _) => kernel.label(),
+        (None, Some(method)) => format!("pool-{}-{}", method.name(), args.size),
+        (None, None) => {
+            return Err(Error::Generic(
+                "give a --kernel or a --pool method".to_string(),
+            ))
+        }
+    };
+
+    let inputs = batch::collect_inputs(&args.input)?;
+    let plan = batch::plan_outputs(&inputs, &args.template, &label, Some(&args.out_dir))?;
+    let color = args.format.color();
+    let (slices, alphas): (Vec<_>, Vec<_>) = plan
+        .iter()
+        .map(|(path, _)| read_image_alpha(path, &color).map(|((_, _, img), alpha)| (img, alpha)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let input = Volume::from_slices(&slices)?;
+
+    let processed = match (&args.kernel, args.pool) {
+        (Some(kernel), _) => {
+            let k = kernel.build(input.ndim())?;
+            match (args.algorithm, args.correlate) {
+                (Algorithm::Direct, false) => volume::convolve(&input, &k, args.padding)?,
+                (Algorithm::Direct, true) => volume::correlate(&input, &k, args.padding)?,
+                (Algorithm::Fft, false) => volume::fft_convolve(&input, &k, args.padding)?,
+                (Algorithm::Fft, true) => volume::fft_correlate(&input, &k, args.padding)?,
+            }
+        }
+        (None, Some(method)) => volume::pool(&input, args.size, args.padding, method)?,
+        // the label above already rejected this
+        (None, None) => unreachable!(),
+    };
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    let [depth, height, width] = [input.shape()[0], input.shape()[1], input.shape()[2]];
+    for (((_, output), img), alpha) in plan.iter().zip(processed.to_slices()?).zip(&alphas) {
+        let bits = args
+            .format
+            .depth
+            .unwrap_or_else(|| BitDepth::for_path(output));
+        save_img(
+            output,
+            &img,
+            &args.format.map,
+            bits,
+            &color,
+            alpha.as_deref(),
+        )?;
+    }
+
+    println!(
+        "Volume {} {}x{}x{}: {:.3?} sec",
+        label,
+        width,
+        height,
+        depth,
+        now.elapsed().as_secs_f32()
+    );
+    Ok(())
+}
+
+fn run_kernel(command: KernelCommand) -> Result<()> {
+    match command {
+        KernelCommand::List => {
+            for entry in registry::kernels() {
+                let params = entry
+                    .params
+                    .iter()
+                    .map(|p| format!(":{}={}", p.name, p.default))
+                    .collect::<String>();
+                println!("{}{}\n    {}", entry.name, params, entry.description);
+            }
+            println!("file:<path>\n    kernel read from a text, csv or json file");
+        }
+        KernelCommand::Show { spec } => {
+            let k = spec.build()?;
+            println!("{} ({}x{})", spec, k.rows(), k.cols());
+            for row in k.values().iter() {
+                let line = row
+                    .iter()
+                    .map(|v| format!("{:>10.5}", v))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                println!("{}", line);
+            }
+        }
+        KernelCommand::Save { spec, output } => {
+            let k = spec.build()?;
+            kernel_file::save_kernel(&k, &output)?;
+            println!(
+                "{} ({}x{}) -> {}",
+                spec,
+                k.rows(),
+                k.cols(),
+                output.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Convolve(_) => "Convolve",
+            Operation::Pool(_) => "Pool",
+            Operation::Fft(_) => "FFT",
+            Operation::Filter(_) => "Filter",
+            Operation::Deconvolve(_) => "Deconvolve",
+            Operation::Spectrum(_) => "Spectrum",
+            Operation::Pipeline(_) => "Pipeline",
+        }
+    }
+
+    /// label used in output names
+    fn label(&self) -> String {
+        match self {
+            Operation::Convolve(args) => args.kernel.label(),
+            Operation::Pool(args) => format!("pooling-{}{}", args.method.name(), args.size),
+            Operation::Fft(args) => format!("fft-{}", args.kernel.label()),
+            Operation::Filter(args) => band_label(args.band),
+            Operation::Deconvolve(args) => format!("deconvolve-{}", args.kernel.label()),
+            Operation::Spectrum(args) if args.phase => "phase".to_string(),
+            Operation::Spectrum(_) => "spectrum".to_string(),
+            Operation::Pipeline(args) => args.pipeline.label().to_string(),
+        }
+    }
+
+    /// check the arguments before any image is read
+    fn validate(&self) -> Result<()> {
+        match self {
+            Operation::Convolve(ConvolveArgs { kernel, .. })
+            | Operation::Fft(FftArgs { kernel, .. }) => {
+                kernel.build()?;
+            }
+            Operation::Deconvolve(args) => {
+                args.kernel.build()?;
+                if args.nsr < 0. {
+                    return Err(Error::Generic("nsr must not be negative".to_string()));
+                }
+            }
+            Operation::Pool(args) => {
+                if args.size == 0 {
+                    return Err(Error::Generic("pool size must be at least 1".to_string()));
+                }
+            }
+            Operation::Filter(_) | Operation::Spectrum(_) | Operation::Pipeline(_) => {}
+        }
+        Ok(())
+    }
+
+    /// read `input`, apply the operation and write the result to `output`
+    fn process(&self, input: &Path, output: &Path, format: &FormatArgs) -> Result<()> {
+        let color = format.color();
+        let ((_, _, img), alpha) = read_image_alpha(input, &color)?;
+
+        let processed = match self {
+            Operation::Convolve(args) => {
+                let k = args.kernel.build()?;
+                match (args.algorithm, args.correlate) {
+                    (Algorithm::Direct, false) => convolve::convolve_2d(&img, &k, args.padding),
+                    (Algorithm::Direct, true) => convolve::correlate_2d(&img, &k, args.padding),
+                    (Algorithm::Fft, false) => convolve::fft_convolve_2d(&img, &k, args.padding),
+                    (Algorithm::Fft, true) => convolve::fft_correlate_2d(&img, &k, args.padding),
+                }
+            }
+            Operation::Pool(args) => args.method.pool(&img, args.size),
+            Operation::Pipeline(args) => args.pipeline.run(&img),
+            Operation::Fft(args) => {
+                let k = args.kernel.build()?;
+                match args.backend {
+                    Backend::Rustfft => convolve::fft_conv_2d_fast(&img, k.values()),
+                    Backend::Handwritten => convolve::fft_conv_2d(&img, k.values()),
+                }
+            }
+            Operation::Filter(args) => {
+                FrequencyFilter::new(args.band, args.response).apply(&img, args.padding)
+            }
+            Operation::Deconvolve(args) => {
+                let k = args.kernel.build()?;
+                match args.method {
+                    Deconvolution::Wiener => deconvolve::wiener(&img, &k, args.nsr, args.padding),
+                    Deconvolution::RichardsonLucy => {
+                        deconvolve::richardson_lucy(&img, &k, args.iterations, args.padding)
+                    }
+                }
+            }
+            Operation::Spectrum(args) => {
+                let windowed = args.window.apply_2d_corrected(&img, args.correction);
+                let f = spectrum::spectrum(&windowed, args.backend);
+                if args.phase {
+                    spectrum::phase(&f)
+                } else {
+                    spectrum::log_magnitude(&f)
+                }
+            }
+        };
+
+        // the spectrum can be larger than the input, its alpha is left out
+        let alpha = match self {
+            Operation::Spectrum(_) => None,
+            _ => alpha.as_deref(),
+        };
+        let depth = format.depth.unwrap_or_else(|| BitDepth::for_path(output));
+        save_img(output, &processed, &format.map, depth, &color, alpha)
+    }
+}
+
+/// label for a frequency band, such as `lowpass-0.05`
+fn band_label(band: Passband) -> String {
+    match band {
+        Passband::LowPass(c) => format!("lowpass-{}", c),
+        Passband::HighPass(c) => format!("highpass-{}", c),
+        Passband::BandPass(low, high) => format!("bandpass-{}-{}", low, high),
+        Passband::Notch { u, v, radius } => format!("notch-{}-{}-{}", u, v, radius),
+    }
+}
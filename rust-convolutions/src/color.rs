@@ -0,0 +1,260 @@
+//! Turning colour pixels into grey values
+//!
+//! Integer images store sRGB encoded values. The weighted conversions work
+//! on those directly, `linear` decodes them to linear light first and the
+//! result is encoded again when saved to an integer format. Float images
+//! (tiff, exr and pfm) are taken to be linear already.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grayscale {
+    /// 0.2989 r + 0.5870 g + 0.1140 b on the encoded values
+    Bt601,
+    /// 0.2126 r + 0.7152 g + 0.0722 b on the encoded values
+    Bt709,
+    /// mean of the three channels
+    Average,
+    /// one channel, 0 red, 1 green and 2 blue
+    Channel(usize),
+    /// bt709 weights on sRGB decoded values, giving linear luminance
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alpha {
+    /// ignore the alpha channel
+    Drop,
+    /// multiply the grey value by alpha, transparent pixels become 0
+    Premultiply,
+    /// blend over a background grey value
+    Composite(f64),
+}
+
+/// How images are converted on load and encoded on save.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorOptions {
+    pub gray: Grayscale,
+    pub alpha: Alpha,
+}
+
+impl Default for ColorOptions {
+    fn default() -> Self {
+        ColorOptions {
+            gray: Grayscale::Bt601,
+            alpha: Alpha::Drop,
+        }
+    }
+}
+
+/// Parses `bt601`, `bt709`, `average`, `red`, `green`, `blue` and `linear`.
+impl FromStr for Grayscale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bt601" => Ok(Grayscale::Bt601),
+            "bt709" => Ok(Grayscale::Bt709),
+            "average" => Ok(Grayscale::Average),
+            "red" => Ok(Grayscale::Channel(0)),
+            "green" => Ok(Grayscale::Channel(1)),
+            "blue" => Ok(Grayscale::Channel(2)),
+            "linear" => Ok(Grayscale::Linear),
+            _ => Err(format!(
+                "unknown conversion '{}', expected bt601, bt709, average, red, green, blue or linear",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses `drop`, `premultiply` and `composite[:<background>]`, the
+/// background defaulting to 1 (white).
+impl FromStr for Alpha {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<&str>>().as_slice() {
+            ["drop"] => Ok(Alpha::Drop),
+            ["premultiply"] => Ok(Alpha::Premultiply),
+            ["composite"] => Ok(Alpha::Composite(1.)),
+            ["composite", value] => value
+                .parse::<f64>()
+                .map(Alpha::Composite)
+                .map_err(|_| format!("'{}' is not a number", value)),
+            _ => Err(format!(
+                "unknown alpha handling '{}', expected drop, premultiply or composite:<background>",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorOptions {
+    /// Grey value of an rgba pixel. `encoded` is true for pixels read from
+    /// integer formats, which `linear` decodes first.
+    pub fn gray_of(&self, [r, g, b, a]: [f32; 4], encoded: bool) -> f64 {
+        let (r, g, b) = (r as f64, g as f64, b as f64);
+        let decode = |v: f64| {
+            if encoded {
+                srgb_to_linear(v)
+            } else {
+                v
+            }
+        };
+
+        let gray = match self.gray {
+            Grayscale::Linear => 0.2126 * decode(r) + 0.7152 * decode(g) + 0.0722 * decode(b),
+            // grey pixels are kept exactly
+            _ if r == g && g == b => r,
+            Grayscale::Bt601 => 0.2989 * r + 0.5870 * g + 0.1140 * b,
+            Grayscale::Bt709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            Grayscale::Average => (r + g + b) / 3.,
+            Grayscale::Channel(c) => [r, g, b][c],
+        };
+
+        let a = a as f64;
+        match self.alpha {
+            Alpha::Drop => gray,
+            Alpha::Premultiply => gray * a,
+            Alpha::Composite(background) => gray * a + background * (1. - a),
+        }
+    }
+
+    /// Undo the `linear` decode for a value written to an integer format,
+    /// other conversions are left alone.
+    pub fn encode(&self, v: f64) -> f64 {
+        match self.gray {
+            Grayscale::Linear => linear_to_srgb(v),
+            _ => v,
+        }
+    }
+}
+
+/// sRGB transfer function inverse, odd so negative values survive
+pub fn srgb_to_linear(v: f64) -> f64 {
+    let x = v.abs();
+    let linear = if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    };
+    linear.copysign(v)
+}
+
+/// sRGB transfer function, odd so negative values survive
+pub fn linear_to_srgb(v: f64) -> f64 {
+    let x = v.abs();
+    let encoded = if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    };
+    encoded.copysign(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPS: f64 = 1e-9;
+
+    fn gray(gray: Grayscale, pixel: [f32; 4], encoded: bool) -> f64 {
+        ColorOptions {
+            gray,
+            alpha: Alpha::Drop,
+        }
+        .gray_of(pixel, encoded)
+    }
+
+    #[test]
+    fn weighted_conversions() {
+        let pixel = [0.5, 0.25, 1., 1.];
+        let cases = [
+            ("bt601", 0.2989 * 0.5 + 0.5870 * 0.25 + 0.1140),
+            ("bt709", 0.2126 * 0.5 + 0.7152 * 0.25 + 0.0722),
+            ("average", 1.75 / 3.),
+            ("red", 0.5),
+            ("green", 0.25),
+            ("blue", 1.),
+        ];
+        for (name, expected) in cases {
+            let g = gray(name.parse().unwrap(), pixel, true);
+            assert!((g - expected).abs() < EPS, "{}: {}", name, g);
+        }
+
+        // grey stays exactly grey, even though the bt601 weights do not
+        // quite sum to one
+        for name in ["bt601", "bt709", "average", "green"] {
+            assert_eq!(gray(name.parse().unwrap(), [0.3; 4], true), 0.3f32 as f64);
+        }
+        assert!("luma".parse::<Grayscale>().is_err());
+    }
+
+    #[test]
+    fn linear_decodes_integer_pixels_only() {
+        let pixel = [0.5, 0.25, 1., 1.];
+        let expected = 0.2126 * srgb_to_linear(0.5)
+            + 0.7152 * srgb_to_linear(0.25)
+            + 0.0722 * srgb_to_linear(1.);
+        assert!((gray(Grayscale::Linear, pixel, true) - expected).abs() < EPS);
+
+        // float pixels are already linear
+        let bt709 = gray(Grayscale::Bt709, pixel, false);
+        assert!((gray(Grayscale::Linear, pixel, false) - bt709).abs() < EPS);
+
+        let options = ColorOptions {
+            gray: Grayscale::Linear,
+            alpha: Alpha::Drop,
+        };
+        let g = options.gray_of([0.5; 4], true);
+        assert!((options.encode(g) - 0.5).abs() < 1e-6);
+        assert_eq!(ColorOptions::default().encode(0.25), 0.25);
+    }
+
+    #[test]
+    fn alpha_handling() {
+        let pixel = [0.8, 0.8, 0.8, 0.25];
+        let with = |alpha| {
+            ColorOptions {
+                gray: Grayscale::Average,
+                alpha,
+            }
+            .gray_of(pixel, true)
+        };
+        let v = 0.8f32 as f64;
+        assert_eq!(with(Alpha::Drop), v);
+        assert!((with(Alpha::Premultiply) - v * 0.25).abs() < EPS);
+        assert!((with(Alpha::Composite(0.)) - v * 0.25).abs() < EPS);
+        assert!((with("composite".parse().unwrap()) - (v * 0.25 + 0.75)).abs() < EPS);
+        assert!("composite:grey".parse::<Alpha>().is_err());
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        // known points of the curve
+        assert!((srgb_to_linear(0.5) - 0.214041140).abs() < EPS);
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-12);
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-12);
+
+        for i in -100..=200 {
+            let v = i as f64 / 100.;
+            assert!(
+                (linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-12,
+                "{}",
+                v
+            );
+            assert!(
+                (srgb_to_linear(linear_to_srgb(v)) - v).abs() < 1e-12,
+                "{}",
+                v
+            );
+            assert_eq!(srgb_to_linear(-v), -srgb_to_linear(v));
+        }
+
+        // the two pieces meet at the thresholds
+        assert!((srgb_to_linear(0.04045 + 1e-9) - srgb_to_linear(0.04045)).abs() < 1e-6);
+        assert!((linear_to_srgb(0.0031308 + 1e-9) - linear_to_srgb(0.0031308)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,291 @@
+//! Convolution of 1d signals
+//!
+//! All methods compute the same true (flipped) convolution:
+//!
+//! - `direct` sums the products, which is quickest for short kernels
+//! - `fft` transforms the whole signal at once
+//! - `overlap_add` and `overlap_save` transform the signal in blocks with
+//!   an fft a few times the kernel length, which suits long signals such
+//!   as audio
+//!
+//! `convolve` adds output modes and padding on top, and picks a method
+//! from the sizes when asked for `Method::Auto`.
+
+use crate::padding::{self, Padding};
+use num::complex::Complex;
+use num::Zero;
+use rustfft::FftPlanner;
+use std::str::FromStr;
+
+/// kernels up to this length are summed directly by `Method::Auto`
+const DIRECT_MAX_KERNEL: usize = 32;
+
+/// how much of the output to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// every output the kernel overlaps, `n + m - 1` samples
+    Full,
+    /// `n` samples lined up with the input, the kernel anchored at its
+    /// centre `m / 2` like `convolve::convolve_1d`
+    Same,
+    /// only outputs where the kernel lies inside the input, `n - m + 1`
+    /// samples
+    Valid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Direct,
+    Fft,
+    OverlapAdd,
+    OverlapSave,
+    /// direct for short kernels, overlap-save for signals much longer
+    /// than the kernel and one fft otherwise
+    Auto,
+}
+
+/// Parses `full`, `same` and `valid`.
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(OutputMode::Full),
+            "same" => Ok(OutputMode::Same),
+            "valid" => Ok(OutputMode::Valid),
+            _ => Err(format!(
+                "unknown output mode '{}', expected full, same or valid",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses `direct`, `fft`, `overlap-add`, `overlap-save` and `auto`.
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(Method::Direct),
+            "fft" => Ok(Method::Fft),
+            "overlap-add" | "ola" => Ok(Method::OverlapAdd),
+            "overlap-save" | "ols" => Ok(Method::OverlapSave),
+            "auto" => Ok(Method::Auto),
+            _ => Err(format!(
+                "unknown method '{}', expected direct, fft, overlap-add, overlap-save or auto",
+                s
+            )),
+        }
+    }
+}
+
+/// True convolution of `input` with `kernel`. Samples beyond the ends of
+/// the input come from `mode`, so with zero padding `Full` is the usual
+/// linear convolution. `Valid` never reads outside the input and is empty
+/// when the kernel is longer than the input.
+pub fn convolve(
+    input: &[f64],
+    kernel: &[f64],
+    output: OutputMode,
+    mode: Padding,
+    method: Method,
+) -> Vec<f64> {
+    convolve_anchored(input, kernel, kernel.len() / 2, output, mode, method)
+}
+
+/// Cross-correlation, the convolution with the reversed kernel. In `Same`
+/// mode the kernel is anchored at `m / 2` like `convolve::correlate_1d`.
+pub fn correlate(
+    input: &[f64],
+    kernel: &[f64],
+    output: OutputMode,
+    mode: Padding,
+    method: Method,
+) -> Vec<f64> {
+    let m = kernel.len();
+    let reversed = kernel.iter().rev().copied().collect::<Vec<f64>>();
+    convolve_anchored(input, &reversed, m - 1 - m / 2, output, mode, method)
+}
+
+/// `convolve` with the `Same` output lined up on kernel index `anchor`
+fn convolve_anchored(
+    input: &[f64],
+    kernel: &[f64],
+    anchor: usize,
+    output: OutputMode,
+    mode: Padding,
+    method: Method,
+) -> Vec<f64> {
+    let m = kernel.len();
+    if input.is_empty() || m == 0 {
+        return vec![];
+    }
+
+    // pad so the outputs wanted are exactly the valid part
+    let (left, right) = match output {
+        OutputMode::Full => (m - 1, m - 1),
+        OutputMode::Same => (m - 1 - anchor, anchor),
+        OutputMode::Valid => (0, 0),
+    };
+    let padded = pad_1d(input, left, right, mode);
+    if padded.len() < m {
+        return vec![];
+    }
+
+    let full = match method {
+        Method::Direct => direct(&padded, kernel),
+        Method::Fft => fft(&padded, kernel),
+        Method::OverlapAdd => overlap_add(&padded, kernel, block_fft_size(m)),
+        Method::OverlapSave => overlap_save(&padded, kernel, block_fft_size(m)),
+        Method::Auto => {
+            if m <= DIRECT_MAX_KERNEL {
+                direct(&padded, kernel)
+            } else if padded.len() > 16 * m {
+                overlap_save(&padded, kernel, block_fft_size(m))
+            } else {
+                fft(&padded, kernel)
+            }
+        }
+    };
+
+    full[m - 1..padded.len()].to_vec()
+}
+
+/// full linear convolution by summing products, `n + m - 1` samples
+pub fn direct(input: &[f64], kernel: &[f64]) -> Vec<f64> {
+    if input.is_empty() || kernel.is_empty() {
+        return vec![];
+    }
+
+    let mut out = vec![0.; input.len() + kernel.len() - 1];
+    for (i, x) in input.iter().enumerate() {
+        for (j, k) in kernel.iter().enumerate() {
+            out[i + j] += x * k;
+        }
+    }
+    out
+}
+
+/// full linear convolution with one fft the size of the result
+pub fn fft(input: &[f64], kernel: &[f64]) -> Vec<f64> {
+    if input.is_empty() || kernel.is_empty() {
+        return vec![];
+    }
+
+    let len = input.len() + kernel.len() - 1;
+    let size = len.next_power_of_two();
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(size);
+    let inverse = planner.plan_fft_inverse(size);
+
+    let mut x = to_complex(input, size);
+    let mut k = to_complex(kernel, size);
+    forward.process(&mut x);
+    forward.process(&mut k);
+    for (a, b) in x.iter_mut().zip(k.iter()) {
+        *a *= b;
+    }
+    inverse.process(&mut x);
+
+    x[..len].iter().map(|v| v.re / size as f64).collect()
+}
+
+/// Full linear convolution by overlap-add. The input is cut into blocks
+/// of `fft_size - m + 1` samples, each block is convolved with one fft
+/// and the tails of neighbouring blocks are added together.
+pub fn overlap_add(input: &[f64], kernel: &[f64], fft_size: usize) -> Vec<f64> {
+    let m = kernel.len();
+    if input.is_empty() || m == 0 {
+        return vec![];
+    }
+    let fft_size = fft_size.max(m).next_power_of_two();
+    let block = fft_size - m + 1;
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(fft_size);
+    let inverse = planner.plan_fft_inverse(fft_size);
+    let mut k = to_complex(kernel, fft_size);
+    forward.process(&mut k);
+
+    let mut out = vec![0.; input.len() + m - 1];
+    for (b, chunk) in input.chunks(block).enumerate() {
+        let mut x = to_complex(chunk, fft_size);
+        forward.process(&mut x);
+        for (a, h) in x.iter_mut().zip(k.iter()) {
+            *a *= h;
+        }
+        inverse.process(&mut x);
+
+        let start = b * block;
+        for (i, v) in x[..chunk.len() + m - 1].iter().enumerate() {
+            out[start + i] += v.re / fft_size as f64;
+        }
+    }
+    out
+}
+
+/// Full linear convolution by overlap-save. Windows of `fft_size` samples
+/// overlapping by `m - 1` are convolved circularly, and the first `m - 1`
+/// outputs of each, which wrapped around, are thrown away.
+pub fn overlap_save(input: &[f64], kernel: &[f64], fft_size: usize) -> Vec<f64> {
+    let m = kernel.len();
+    if input.is_empty() || m == 0 {
+        return vec![];
+    }
+    let fft_size = fft_size.max(m).next_power_of_two();
+    let step = fft_size - m + 1;
+
+    let mut planner = FftPlanner::new();
+    let forward = planner.plan_fft_forward(fft_size);
+    let inverse = planner.plan_fft_inverse(fft_size);
+    let mut k = to_complex(kernel, fft_size);
+    forward.process(&mut k);
+
+    // m - 1 zeros of history before the signal, and enough after it to
+    // flush the tail
+    let len = input.len() + m - 1;
+    let mut padded = vec![0.; m - 1];
+    padded.extend_from_slice(input);
+    padded.resize(len.div_ceil(step) * step + m - 1, 0.);
+
+    let mut out = Vec::with_capacity(len + step);
+    let mut start = 0;
+    while out.len() < len {
+        let mut x = to_complex(&padded[start..start + fft_size], fft_size);
+        forward.process(&mut x);
+        for (a, h) in x.iter_mut().zip(k.iter()) {
+            *a *= h;
+        }
+        inverse.process(&mut x);
+
+        out.extend(x[m - 1..].iter().map(|v| v.re / fft_size as f64));
+        start += step;
+    }
+    out.truncate(len);
+    out
+}
+
+/// fft size for block convolution, a power of 2 around 8 kernel lengths
+pub fn block_fft_size(kernel_len: usize) -> usize {
+    (8 * kernel_len).next_power_of_two().max(64)
+}
+
+/// pad a signal on each side with `mode`, reflecting without repeating
+/// the end sample
+pub fn pad_1d(input: &[f64], left: usize, right: usize, mode: Padding) -> Vec<f64> {
+    if left == 0 && right == 0 {
+        return input.to_owned();
+    }
+    let row = vec![input.to_owned()];
+    padding::pad_sides(&row, 0, 0, left, right, mode).remove(0)
+}
+
+/// copy real values into a complex buffer of `size`, padded with zeros
+fn to_complex(values: &[f64], size: usize) -> Vec<Complex<f64>> {
+    let mut out = vec![Complex::zero(); size];
+    for (o, v) in out.iter_mut().zip(values.iter()) {
+        *o = Complex::new(*v, 0.);
+    }
+    out
+}
@@ -1,14 +1,18 @@
 use crate::dft;
-use crate::padding;
+use crate::kernel::Kernel;
+use crate::padding::{self, Padding};
 use num::complex::Complex;
 use num::Zero;
 use rustfft::FftPlanner;
 
-/// perform a basic convolution with no padding
-pub fn conv(input: &Vec<f64>, kernel: &Vec<f64>) -> Vec<f64> {
+/// perform a basic convolution with no padding, giving the
+/// `input.len() - kernel.len() + 1` outputs where the kernel fits. The
+/// kernel is not flipped, so this is a cross-correlation, see
+/// `correlate_1d`, `convolve_1d` and the `conv1d` module.
+pub fn conv(input: &[f64], kernel: &[f64]) -> Vec<f64> {
     let mut out: Vec<f64> = vec![];
 
-    for i in 0..input.len() - kernel.len() {
+    for i in 0..(input.len() + 1).saturating_sub(kernel.len()) {
         // internal dot product
         let mut val = 0.;
         for j in 0..kernel.len() {
@@ -16,10 +20,10 @@ pub fn conv(input: &Vec<f64>, kernel: &Vec<f64>) -> Vec<f64> {
         }
         out.push(val);
     }
-    return out;
+    out
 }
 
-pub fn conv_pad(input: &Vec<f64>, kernel: &Vec<f64>) -> Vec<f64> {
+pub fn conv_pad(input: &[f64], kernel: &[f64]) -> Vec<f64> {
     // pad the input with zeros on both sides
     let mut padded_input: Vec<f64> = vec![0.; input.len() + kernel.len()];
     for i in 0..input.len() {
@@ -36,16 +40,17 @@ pub fn conv_pad(input: &Vec<f64>, kernel: &Vec<f64>) -> Vec<f64> {
         }
         out.push(val);
     }
-    return out;
+    out
 }
 
 /// Use basic matrix multiplication to calculate the step-wise convolution of
 /// an input vector and a kernel. The output will be the same size as the
-/// param `input`
-pub fn conv_2d(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+/// param `input`. The kernel is not flipped, so this is a cross-correlation,
+/// see `correlate_2d` and `convolve_2d`.
+pub fn conv_2d(input: &[Vec<f64>], kernel: &[Vec<f64>]) -> Vec<Vec<f64>> {
     // create zero padded version of input list to account for
     // kernel size
-    let mut padded = padding::reflection_pad(input, kernel.len());
+    let padded = padding::reflection_pad(input, kernel.len());
 
     let mut out = vec![vec![0.0; input[0].len()]; input.len()];
 
@@ -64,30 +69,30 @@ pub fn conv_2d(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
         }
     }
 
-    return out;
+    out
 }
 
-/// Uses the fast fourier transform algorithm to calculate the
-/// convolution between an image `input` and a `kernel`
-pub fn fft_conv_2d(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+/// Convolve with the handwritten fft on the flattened image. The image
+/// and the kernel are each laid out as one long row and zero-padded to
+/// the next power of 2, so this is a 1d circular convolution: the kernel
+/// rows sit end to end rather than on neighbouring image rows, values
+/// wrap from the end of one image row into the next, and the kernel is
+/// anchored at its first weight. Like `fft_conv_2d_fast` the real part of
+/// the inverse transform is returned, so negative responses keep their
+/// sign. For a true 2d convolution use `fft_convolve_2d`.
+pub fn fft_conv_2d(input: &[Vec<f64>], kernel: &[Vec<f64>]) -> Vec<Vec<f64>> {
     // get image dimmensions
     let width = input[0].len();
     let height = input.len();
 
     // flatten image
-    let mut image = input.to_owned().into_iter().flatten().collect::<Vec<f64>>();
+    let image = input.iter().flatten().copied().collect::<Vec<f64>>();
 
     // flatten kernel
-    let mut flat_kernel = kernel
-        .to_owned()
-        .into_iter()
-        .flatten()
-        .collect::<Vec<f64>>();
+    let flat_kernel = kernel.iter().flatten().copied().collect::<Vec<f64>>();
 
     let mut padded_kernel = vec![0.; image.len()];
-    for i in 0..flat_kernel.len() {
-        padded_kernel[i] = flat_kernel[i];
-    }
+    padded_kernel[..flat_kernel.len()].copy_from_slice(&flat_kernel);
 
     // perform ffts
     let image_fft = dft::fft(&image);
@@ -107,35 +112,27 @@ pub fn fft_conv_2d(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Vec<f64
     let mut result_norm = vec![vec![0.; width]; height];
     for y in 0..height {
         for x in 0..width {
-            result_norm[y][x] = result_ifft[y as usize * width + x as usize]
+            result_norm[y][x] = result_ifft[y * width + x]
         }
     }
 
-    return result_norm;
+    result_norm
 }
 
 /// uses faster fft algorithms to calculate the convolution between an image and a
-/// kernel.
-pub fn fft_conv_2d_fast(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+/// kernel. The result is not rescaled, pick a `mapping::ValueMap` when saving.
+pub fn fft_conv_2d_fast(input: &[Vec<f64>], kernel: &[Vec<f64>]) -> Vec<Vec<f64>> {
     // get image dimmensions
     let width = input[0].len();
     let height = input.len();
 
     // flatten image
     let mut image = input
-        .to_owned()
-        .into_iter()
+        .iter()
         .flatten()
-        .map(|v| Complex::new(v, 0.0))
+        .map(|v| Complex::new(*v, 0.0))
         .collect::<Vec<Complex<f64>>>();
 
-    // flatten kernel
-    let flat_kernel = kernel
-        .to_owned()
-        .into_iter()
-        .flatten()
-        .collect::<Vec<f64>>();
-
     // let mut padded_kernel = vec![Complex::zero(); image.len()];
     // for i in 0..flat_kernel.len() {
     //     padded_kernel[i] = Complex::new(flat_kernel[i], 0.0);
@@ -147,9 +144,8 @@ pub fn fft_conv_2d_fast(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Ve
     let kernel_height = kernel.len();
     let pad_x = kernel_width / 2;
     let pad_y = kernel_height / 2;
-    for y in 0..kernel_height {
-        for x in 0..kernel_width {
-            let kernel_val = kernel[y][x];
+    for (y, row) in kernel.iter().enumerate() {
+        for (x, &kernel_val) in row.iter().enumerate() {
             let padded_index = (y + pad_y) * width + (x + pad_x);
             padded_kernel[padded_index] = Complex::new(kernel_val, 0.0);
             if x < pad_x {
@@ -186,35 +182,241 @@ pub fn fft_conv_2d_fast(input: &Vec<Vec<f64>>, kernel: &Vec<Vec<f64>>) -> Vec<Ve
     // perform ifft
     f.process(&mut result_fft);
 
-    // reconstruct the 2d vector
-    let max_value = result_fft.iter().map(|c| c.norm()).fold(0.0, f64::max);
+    // reconstruct the 2d vector, rustfft leaves the inverse unscaled
+    let scale = result_fft.len() as f64;
     let mut result_norm = vec![vec![0.; width]; height];
     for y in 0..height {
         for x in 0..width {
-            result_norm[y][x] = result_fft[y as usize * width + x as usize].norm() / max_value
+            result_norm[y][x] = result_fft[y * width + x].re / scale
+        }
+    }
+
+    result_norm
+}
+
+/// Cross-correlation of a signal with a kernel, without flipping it:
+/// `out[i] = sum_j kernel[j] * input[i + j - a]` where `a = kernel.len() / 2`.
+/// Values outside the input are zero, and the output is the same size as
+/// the input.
+pub fn correlate_1d(input: &[f64], kernel: &[f64]) -> Vec<f64> {
+    let anchor = kernel.len() / 2;
+    let mut out = vec![0.; input.len()];
+
+    for (i, o) in out.iter_mut().enumerate() {
+        let mut val = 0.;
+        for (j, k) in kernel.iter().enumerate() {
+            if let Some(v) = (i + j).checked_sub(anchor).and_then(|n| input.get(n)) {
+                val += v * k;
+            }
+        }
+        *o = val;
+    }
+    out
+}
+
+/// True convolution of a signal with a kernel, which is flipped:
+/// `out[i] = sum_j kernel[j] * input[i - j + a]` where `a = kernel.len() / 2`.
+/// Values outside the input are zero, and the output is the same size as
+/// the input.
+pub fn convolve_1d(input: &[f64], kernel: &[f64]) -> Vec<f64> {
+    let anchor = kernel.len() / 2;
+    let mut out = vec![0.; input.len()];
+
+    for (i, o) in out.iter_mut().enumerate() {
+        let mut val = 0.;
+        for (j, k) in kernel.iter().enumerate() {
+            if let Some(v) = (i + anchor).checked_sub(j).and_then(|n| input.get(n)) {
+                val += v * k;
+            }
+        }
+        *o = val;
+    }
+    out
+}
+
+/// Cross-correlation of an image with a kernel, without flipping it. With
+/// the kernel anchor at `(ar, ac)`:
+/// `out[i][j] = sum_nm kernel[n][m] * input[i + n - ar][j + m - ac]`
+/// The input is padded with `mode`, and the output is the same size as
+/// the input. For symmetric kernels this matches `convolve_2d`.
+pub fn correlate_2d(input: &[Vec<f64>], kernel: &Kernel, mode: Padding) -> Vec<Vec<f64>> {
+    let padded = pad_for_kernel(input, kernel, mode);
+    let mut out = vec![vec![0.0; input[0].len()]; input.len()];
+
+    for i in 0..input.len() {
+        for j in 0..input[0].len() {
+            let mut sum = 0.0;
+            for n in 0..kernel.rows() {
+                for m in 0..kernel.cols() {
+                    sum += padded[i + n][j + m] * kernel[n][m];
+                }
+            }
+            out[i][j] = sum;
         }
     }
 
-    return result_norm;
+    out
 }
 
-pub fn print_vec_2d(list: &Vec<Vec<f64>>) {
+/// True convolution of an image with a kernel, which is the correlation
+/// with the kernel rotated by 180 degrees around its anchor:
+/// `out[i][j] = sum_nm kernel[n][m] * input[i - n + ar][j - m + ac]`
+/// Asymmetric kernels such as `edge_x` and `emboss` give mirrored results
+/// compared to `correlate_2d`.
+pub fn convolve_2d(input: &[Vec<f64>], kernel: &Kernel, mode: Padding) -> Vec<Vec<f64>> {
+    correlate_2d(input, &kernel.flip(), mode)
+}
+
+/// Same result as `correlate_2d`, computed with the fft. The padded input
+/// is transformed at its exact size, so the circular wrap of the fft never
+/// reaches the output region.
+pub fn fft_correlate_2d(input: &[Vec<f64>], kernel: &Kernel, mode: Padding) -> Vec<Vec<f64>> {
+    let padded = pad_for_kernel(input, kernel, mode);
+    let (rows, cols) = (padded.len(), padded[0].len());
+
+    // correlating is multiplying by the spectrum of the flipped kernel
+    let flipped = kernel.flip();
+    let mut padded_kernel = vec![vec![Complex::zero(); cols]; rows];
+    for n in 0..kernel.rows() {
+        for m in 0..kernel.cols() {
+            padded_kernel[n][m] = Complex::new(flipped[n][m], 0.0);
+        }
+    }
+
+    let image = padded
+        .iter()
+        .map(|row| row.iter().map(|v| Complex::new(*v, 0.0)).collect())
+        .collect::<Vec<Vec<Complex<f64>>>>();
+
+    let image_fft = dft::fft_2d(&image);
+    let kernel_fft = dft::fft_2d(&padded_kernel);
+
+    // multiply the fft together
+    let result_fft = image_fft
+        .iter()
+        .zip(kernel_fft.iter())
+        .map(|(a, b)| a.iter().zip(b.iter()).map(|(x, y)| x * y).collect())
+        .collect::<Vec<Vec<Complex<f64>>>>();
+
+    let result = dft::ifft_2d(&result_fft);
+
+    // the linear result starts one kernel size in
+    let mut out = vec![vec![0.0; input[0].len()]; input.len()];
+    for i in 0..input.len() {
+        for j in 0..input[0].len() {
+            out[i][j] = result[i + kernel.rows() - 1][j + kernel.cols() - 1].re;
+        }
+    }
+
+    out
+}
+
+/// Same result as `convolve_2d`, computed with the fft.
+pub fn fft_convolve_2d(input: &[Vec<f64>], kernel: &Kernel, mode: Padding) -> Vec<Vec<f64>> {
+    fft_correlate_2d(input, &kernel.flip(), mode)
+}
+
+/// pad the input so the kernel anchor can sit on every input pixel
+fn pad_for_kernel(input: &[Vec<f64>], kernel: &Kernel, mode: Padding) -> Vec<Vec<f64>> {
+    let (ar, ac) = kernel.anchor();
+    padding::pad_sides(
+        input,
+        ar,
+        kernel.rows() - 1 - ar,
+        ac,
+        kernel.cols() - 1 - ac,
+        mode,
+    )
+}
+
+pub fn print_vec_2d(list: &[Vec<f64>]) {
     for i in 0..list.len() {
         if i == 0 {
             print!("[[");
         } else {
             print!(" [");
         }
-        for j in 0..list[0].len() {
-            if (list[i][j] < 10.) {
+        for v in &list[i] {
+            if *v < 10.  {
                 print!("0");
             }
-            print!("{:.1}, ", list[i][j]);
+            print!("{:.1}, ", v);
         }
-        if (i == list.len() - 1) {
-            print!("]] ({:?}x{:?})\n", list.len(), list[0].len());
+        if i == list.len() - 1  {
+            println!("]] ({:?}x{:?})", list.len(), list[0].len());
         } else {
-            print!("]\n");
+            println!("]");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::KernelConstructors;
+
+    /// a smooth but uneven test image
+    fn image() -> Vec<Vec<f64>> {
+        (0..11)
+            .map(|i| {
+                (0..13)
+                    .map(|j| ((i * 7 + j * 3) as f64 * 0.37).sin() + 0.1 * j as f64)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn max_difference(a: &[Vec<f64>], b: &[Vec<f64>]) -> f64 {
+        a.iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(x, y)| (x - y).abs())
+            .fold(0., f64::max)
+    }
+
+    fn assert_paths_agree(kernel: &Kernel) {
+        let img = image();
+        for mode in [Padding::Zero, Padding::Reflection, Padding::Constant(0.5)] {
+            let direct = convolve_2d(&img, kernel, mode);
+            let fft = fft_convolve_2d(&img, kernel, mode);
+            assert!(max_difference(&direct, &fft) < 1e-9, "convolve {:?}", mode);
+
+            let direct = correlate_2d(&img, kernel, mode);
+            let fft = fft_correlate_2d(&img, kernel, mode);
+            assert!(max_difference(&direct, &fft) < 1e-9, "correlate {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn direct_and_fft_agree_for_edge_x() {
+        assert_paths_agree(&Kernel::edge_x());
+    }
+
+    #[test]
+    fn direct_and_fft_agree_for_emboss() {
+        assert_paths_agree(&Kernel::emboss());
+    }
+
+    #[test]
+    fn direct_and_fft_agree_off_centre() {
+        let kernel = Kernel::new(vec![
+            vec![1., -2., 0.5, 3.],
+            vec![0., 4., -1., 2.],
+            vec![-3., 1., 1.5, 0.],
+        ])
+        .unwrap()
+        .with_anchor(0, 3)
+        .unwrap();
+        assert_paths_agree(&kernel);
+    }
+
+    #[test]
+    fn convolve_flips_asymmetric_kernels() {
+        let img = image();
+        let k = Kernel::edge_x();
+        let convolved = convolve_2d(&img, &k, Padding::Zero);
+        let correlated = correlate_2d(&img, &k.flip(), Padding::Zero);
+        assert!(max_difference(&convolved, &correlated) < 1e-12);
+        assert!(max_difference(&convolved, &correlate_2d(&img, &k, Padding::Zero)) > 0.1);
+    }
+}
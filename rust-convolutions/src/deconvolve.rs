@@ -0,0 +1,180 @@
+//! Undoing a known blur
+//!
+//! Both methods take the point spread function (psf) of the blur as a
+//! kernel, the way it would be passed to `convolve::convolve_2d` to blur
+//! the image. It is normalized to sum to one first.
+
+use crate::convolve;
+use crate::dft;
+use crate::kernel::Kernel;
+use crate::padding::{self, Padding};
+use num::complex::Complex;
+use num::Zero;
+
+/// smallest blurred value divided by in `richardson_lucy`
+const EPSILON: f64 = 1e-12;
+
+/// Wiener deconvolution, `F = G conj(H) / (|H|^2 + nsr)` where `G` is the
+/// spectrum of the image and `H` of the psf. `nsr` is the noise to signal
+/// power ratio, larger values suppress more noise and restore less
+/// detail, 0 is a plain inverse filter. The image is padded by the psf
+/// size with `mode` to reduce ringing from the edges.
+pub fn wiener(input: &[Vec<f64>], psf: &Kernel, nsr: f64, mode: Padding) -> Vec<Vec<f64>> {
+    let psf = psf.normalize();
+    let (py, px) = (psf.rows(), psf.cols());
+    let padded = padding::pad_sides(input, py, py, px, px, mode);
+    let (rows, cols) = (padded.len(), padded[0].len());
+
+    let image = padded
+        .iter()
+        .map(|row| row.iter().map(|v| Complex::new(*v, 0.0)).collect())
+        .collect::<Vec<Vec<Complex<f64>>>>();
+    let g = dft::fft_2d(&image);
+    let h = dft::fft_2d(&psf_at_origin(&psf, rows, cols));
+
+    let restored = g
+        .iter()
+        .zip(h.iter())
+        .map(|(g_row, h_row)| {
+            g_row
+                .iter()
+                .zip(h_row.iter())
+                .map(|(g, h)| {
+                    let denominator = h.norm_sqr() + nsr;
+                    if denominator == 0. {
+                        Complex::zero()
+                    } else {
+                        g * h.conj() / denominator
+                    }
+                })
+                .collect()
+        })
+        .collect::<Vec<Vec<Complex<f64>>>>();
+
+    let result = dft::ifft_2d(&restored);
+    (0..input.len())
+        .map(|i| {
+            (0..input[0].len())
+                .map(|j| result[i + py][j + px].re)
+                .collect()
+        })
+        .collect()
+}
+
+/// Richardson-Lucy deconvolution, starting from the blurred image and
+/// repeating `u = u * correlate(d / convolve(u, psf), psf)` where `d` is
+/// the input. The input should be non-negative. More iterations restore
+/// more detail and amplify more noise.
+pub fn richardson_lucy(
+    input: &[Vec<f64>],
+    psf: &Kernel,
+    iterations: usize,
+    mode: Padding,
+) -> Vec<Vec<f64>> {
+    let psf = psf.normalize();
+    let mut estimate = input.to_owned();
+
+    for _ in 0..iterations {
+        let blurred = convolve::fft_convolve_2d(&estimate, &psf, mode);
+        let ratio = input
+            .iter()
+            .zip(blurred.iter())
+            .map(|(d_row, b_row)| {
+                d_row
+                    .iter()
+                    .zip(b_row.iter())
+                    .map(|(d, b)| d / b.max(EPSILON))
+                    .collect()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        let correction = convolve::fft_correlate_2d(&ratio, &psf, mode);
+
+        for (row, c_row) in estimate.iter_mut().zip(correction.iter()) {
+            for (u, c) in row.iter_mut().zip(c_row.iter()) {
+                *u *= c;
+            }
+        }
+    }
+
+    estimate
+}
+
+/// The psf laid out for a circular convolution of a `rows` by `cols`
+/// image, with its anchor moved to the top left corner and the rest
+/// wrapped around the edges.
+fn psf_at_origin(psf: &Kernel, rows: usize, cols: usize) -> Vec<Vec<Complex<f64>>> {
+    let (ar, ac) = psf.anchor();
+    let mut out = vec![vec![Complex::zero(); cols]; rows];
+    for n in 0..psf.rows() {
+        for m in 0..psf.cols() {
+            let i = (n + rows - ar) % rows;
+            let j = (m + cols - ac) % cols;
+            out[i][j] += psf[n][m];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a pattern on a black border wide enough that blurring with zero
+    /// padding loses nothing off the edges
+    fn image() -> Vec<Vec<f64>> {
+        (0..24)
+            .map(|i| {
+                (0..26)
+                    .map(|j| {
+                        if (6..18).contains(&i) && (6..20).contains(&j) {
+                            ((i * 7 + j * 3) % 11) as f64 / 10.
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// lopsided so a flipped psf would show, with a dominant centre so
+    /// its spectrum has no zeros
+    fn psf() -> Kernel {
+        Kernel::new(vec![vec![0., 1., 2.], vec![1., 6., 0.5], vec![0., 0., 0.]]).unwrap()
+    }
+
+    fn rms(a: &[Vec<f64>], b: &[Vec<f64>]) -> f64 {
+        let n = a.len() * a[0].len();
+        let sum = a
+            .iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(x, y)| (x - y).powi(2))
+            .sum::<f64>();
+        (sum / n as f64).sqrt()
+    }
+
+    #[test]
+    fn wiener_undoes_a_known_blur() {
+        let img = image();
+        let blurred = convolve::fft_convolve_2d(&img, &psf().normalize(), Padding::Zero);
+        assert!(rms(&blurred, &img) > 0.05);
+
+        let restored = wiener(&blurred, &psf(), 1e-10, Padding::Zero);
+        assert!(rms(&restored, &img) < 1e-6);
+    }
+
+    #[test]
+    fn richardson_lucy_converges() {
+        let img = image();
+        let blurred = convolve::fft_convolve_2d(&img, &psf().normalize(), Padding::Zero);
+
+        let errors = [0, 5, 20, 60]
+            .iter()
+            .map(|n| rms(&richardson_lucy(&blurred, &psf(), *n, Padding::Zero), &img))
+            .collect::<Vec<f64>>();
+        assert_eq!(errors[0], rms(&blurred, &img));
+        assert!(errors.windows(2).all(|e| e[1] < e[0]), "{:?}", errors);
+        assert!(errors[3] < errors[0] / 2., "{:?}", errors);
+    }
+}
@@ -1,26 +1,53 @@
-use num::{complex::Complex, integer::sqrt, Zero};
-use std::f64::consts::{E, PI};
+use num::{complex::Complex, Zero};
+use rustfft::{FftDirection, FftPlanner};
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// which fft implementation to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// the rustfft planner
+    Rustfft,
+    /// the recursive fft in this module
+    Handwritten,
+}
+
+/// Parses `rustfft` and `handwritten`.
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rustfft" => Ok(Backend::Rustfft),
+            "handwritten" => Ok(Backend::Handwritten),
+            _ => Err(format!(
+                "unknown fft backend '{}', expected rustfft or handwritten",
+                s
+            )),
+        }
+    }
+}
 
 /// basic discrete fourier transform
-pub fn dft(x: &Vec<Complex<f64>>) -> Vec<Complex<f64>> {
+pub fn dft(x: &[Complex<f64>]) -> Vec<Complex<f64>> {
     let l = x.len();
 
     let mut dft = vec![Complex { re: 0.0, im: 0.0 }; l];
     let c = Complex { re: 0.0, im: -2.0 };
-    for k in 0..l {
+    for (k, d) in dft.iter_mut().enumerate() {
         let mut t = Complex { re: 0.0, im: 0.0 };
-        for n in 0..l {
-            t += x[n] * (c * PI * (k as f64) * (n as f64) / (l as f64)).exp();
+        for (n, v) in x.iter().enumerate() {
+            t += v * (c * PI * (k as f64) * (n as f64) / (l as f64)).exp();
         }
-        dft[k] = t;
+        *d = t;
     }
-    return dft;
+    dft
 }
 
 /// fast fourier transform. Outputs a list that is the length of
 /// the next nearest power of 2. The output is suitable for input into
 /// the ifft algorithm.
-pub fn fft(x: &Vec<f64>) -> Vec<Complex<f64>> {
+pub fn fft(x: &[f64]) -> Vec<Complex<f64>> {
     // convert to complex
     let mut input = x
         .iter()
@@ -30,10 +57,9 @@ pub fn fft(x: &Vec<f64>) -> Vec<Complex<f64>> {
     // pad unitl next power of 2
     input.resize(x.len().next_power_of_two(), Complex::zero());
 
-    let t = fft_helper(&input);
-    return t;
+    fft_helper(&input)
 }
-fn fft_helper(x: &Vec<Complex<f64>>) -> Vec<Complex<f64>> {
+fn fft_helper(x: &[Complex<f64>]) -> Vec<Complex<f64>> {
     let l = x.len();
 
     if l == 1 {
@@ -56,24 +82,21 @@ fn fft_helper(x: &Vec<Complex<f64>>) -> Vec<Complex<f64>> {
         dft[k + l / 2] = even[k] - t;
     }
 
-    return dft;
+    dft
 }
 
 /// perform the inverse fourier transform. This expects that the list
-/// is a power of two in length, the fft function assures that.
-pub fn ifft(x: &Vec<Complex<f64>>) -> Vec<f64> {
+/// is a power of two in length, the fft function assures that. The real
+/// part is returned, keeping the sign of the result.
+pub fn ifft(x: &[Complex<f64>]) -> Vec<f64> {
     if !x.len().is_power_of_two() {
         panic!("Input must have a length that is a power of 2");
     } else {
         let out = ifft_helper(x);
-        return out
-            .to_owned()
-            .into_iter()
-            .map(|v| v.norm())
-            .collect::<Vec<f64>>();
+        out.into_iter().map(|v| v.re).collect::<Vec<f64>>()
     }
 }
-fn ifft_helper(x: &Vec<Complex<f64>>) -> Vec<Complex<f64>> {
+fn ifft_helper(x: &[Complex<f64>]) -> Vec<Complex<f64>> {
     let l = x.len();
 
     if l == 1 {
@@ -96,5 +119,84 @@ fn ifft_helper(x: &Vec<Complex<f64>>) -> Vec<Complex<f64>> {
         idft[k + l / 2] = (even[k] - t) / 2.0;
     }
 
-    return idft;
+    idft
+}
+
+/// 2d version of `fft`, every row and then every column is padded with
+/// zeros to the next power of 2 and transformed with the recursive fft.
+pub fn fft_2d_padded(x: &[Vec<f64>]) -> Vec<Vec<Complex<f64>>> {
+    let rows = x
+        .iter()
+        .map(|row| fft(row))
+        .collect::<Vec<Vec<Complex<f64>>>>();
+    let (height, width) = (x.len().next_power_of_two(), rows[0].len());
+
+    let mut out = vec![vec![Complex::zero(); width]; height];
+    let mut column = vec![Complex::zero(); height];
+    for j in 0..width {
+        for i in 0..rows.len() {
+            column[i] = rows[i][j];
+        }
+        let t = fft_helper(&column);
+        for i in 0..height {
+            out[i][j] = t[i];
+        }
+    }
+
+    out
+}
+
+/// 2d fourier transform of a matrix using rustfft, transforming every row
+/// and then every column. Any size is accepted, no padding is added.
+pub fn fft_2d(x: &[Vec<Complex<f64>>]) -> Vec<Vec<Complex<f64>>> {
+    transform_2d(x, FftDirection::Forward)
+}
+
+/// inverse of `fft_2d`, scaled so that `ifft_2d(&fft_2d(&x))` gives back `x`
+pub fn ifft_2d(x: &[Vec<Complex<f64>>]) -> Vec<Vec<Complex<f64>>> {
+    let scale = (x.len() * x[0].len()) as f64;
+    transform_2d(x, FftDirection::Inverse)
+        .into_iter()
+        .map(|row| row.into_iter().map(|v| v / scale).collect())
+        .collect()
+}
+fn transform_2d(x: &[Vec<Complex<f64>>], direction: FftDirection) -> Vec<Vec<Complex<f64>>> {
+    let (rows, cols) = (x.len(), x[0].len());
+    let mut planner = FftPlanner::new();
+
+    // rows
+    let f = planner.plan_fft(cols, direction);
+    let mut out = x.to_owned();
+    for row in out.iter_mut() {
+        f.process(row);
+    }
+
+    // columns
+    let f = planner.plan_fft(rows, direction);
+    let mut column = vec![Complex::zero(); rows];
+    for j in 0..cols {
+        for (c, row) in column.iter_mut().zip(out.iter()) {
+            *c = row[j];
+        }
+        f.process(&mut column);
+        for (c, row) in column.iter().zip(out.iter_mut()) {
+            row[j] = *c;
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ifft_keeps_the_sign() {
+        let x = vec![1., -2., 3.5, -0.25, 0., 4., -1., 2.];
+        let back = ifft(&fft(&x));
+        for (a, b) in x.iter().zip(back.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
 }
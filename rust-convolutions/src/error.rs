@@ -1,6 +1,5 @@
 //! Main Crate Error
 
-use image::io;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -8,10 +7,38 @@ pub enum Error {
     #[error("Generic {0}")]
     Generic(String),
 
+    // kernel validation errors
+    #[error("Invalid kernel: {0}")]
+    Kernel(String),
+
     // for io errors
     #[error(transparent)]
     IO(#[from] std::io::Error),
 
+    // kernel spec parsing errors
+    #[error("Invalid kernel spec: {0}")]
+    KernelSpec(String),
+
+    // pipeline validation errors
+    #[error("Invalid pipeline: {0}")]
+    Pipeline(String),
+
+    // kernel file and pipeline json errors
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    // pipeline toml errors
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+
+    // float tiff errors
+    #[error(transparent)]
+    Tiff(#[from] tiff::TiffError),
+
+    // wav errors
+    #[error(transparent)]
+    Wav(#[from] hound::Error),
+
     // image errors
     #[error(transparent)]
     ImageError(#[from] image::error::ImageError),
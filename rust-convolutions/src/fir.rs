@@ -0,0 +1,404 @@
+//! Designing 1d FIR filter kernels
+//!
+//! Cutoff frequencies are in cycles per sample, from 0 up to 0.5 at the
+//! Nyquist limit, so a cutoff of `f` Hz at sample rate `r` is `f / r`.
+//! The kernels are ready for `conv1d::convolve` and
+//! `convolve::convolve_1d`, which both flip them. `convolve::conv`
+//! correlates, which only gives the same result for symmetric kernels.
+//!
+//! The windowed-sinc filters take an odd number of taps so they have a
+//! centre tap and a delay of a whole number of samples.
+
+use crate::prelude::*;
+use crate::windowing::WindowFunction;
+use num::complex::Complex;
+use num::Zero;
+use rustfft::FftPlanner;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// A filter to design, see the functions of the same names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirDesign {
+    LowPass {
+        taps: usize,
+        cutoff: f64,
+        window: WindowFunction,
+    },
+    HighPass {
+        taps: usize,
+        cutoff: f64,
+        window: WindowFunction,
+    },
+    BandPass {
+        taps: usize,
+        low: f64,
+        high: f64,
+        window: WindowFunction,
+    },
+    BandStop {
+        taps: usize,
+        low: f64,
+        high: f64,
+        window: WindowFunction,
+    },
+    MovingAverage(usize),
+    Derivative {
+        order: usize,
+        points: usize,
+    },
+}
+
+/// Parses `lowpass:<taps>:<cutoff>`, `highpass:<taps>:<cutoff>`,
+/// `bandpass:<taps>:<low>:<high>` and `bandstop:<taps>:<low>:<high>`, each
+/// optionally followed by a window such as `:kaiser:8` (hamming by
+/// default), and `average:<n>` and `derivative:<order>:<points>`.
+impl FromStr for FirDesign {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<&str>>();
+        let count = |v: &str| {
+            v.parse::<usize>()
+                .map_err(|_| format!("'{}' is not a whole number", v))
+        };
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("'{}' is not a number", v))
+        };
+        // everything after the first `n` parts names the window
+        let window = |n: usize| {
+            if parts.len() > n {
+                parts[n..].join(":").parse::<WindowFunction>()
+            } else {
+                Ok(WindowFunction::Hamming)
+            }
+        };
+
+        let design = match parts.as_slice() {
+            ["lowpass", taps, cutoff, ..] => FirDesign::LowPass {
+                taps: count(taps)?,
+                cutoff: number(cutoff)?,
+                window: window(3)?,
+            },
+            ["highpass", taps, cutoff, ..] => FirDesign::HighPass {
+                taps: count(taps)?,
+                cutoff: number(cutoff)?,
+                window: window(3)?,
+            },
+            ["bandpass", taps, low, high, ..] => FirDesign::BandPass {
+                taps: count(taps)?,
+                low: number(low)?,
+                high: number(high)?,
+                window: window(4)?,
+            },
+            ["bandstop", taps, low, high, ..] => FirDesign::BandStop {
+                taps: count(taps)?,
+                low: number(low)?,
+                high: number(high)?,
+                window: window(4)?,
+            },
+            ["average", n] => FirDesign::MovingAverage(count(n)?),
+            ["derivative", order, points] => FirDesign::Derivative {
+                order: count(order)?,
+                points: count(points)?,
+            },
+            _ => {
+                return Err(format!(
+                    "unknown filter '{}', expected lowpass:<taps>:<cutoff>, highpass:<taps>:<cutoff>, bandpass:<taps>:<low>:<high>, bandstop:<taps>:<low>:<high>, average:<n> or derivative:<order>:<points>",
+                    s
+                ))
+            }
+        };
+        Ok(design)
+    }
+}
+
+impl FirDesign {
+    pub fn build(&self) -> Result<Vec<f64>> {
+        match *self {
+            FirDesign::LowPass {
+                taps,
+                cutoff,
+                window,
+            } => lowpass(taps, cutoff, window),
+            FirDesign::HighPass {
+                taps,
+                cutoff,
+                window,
+            } => highpass(taps, cutoff, window),
+            FirDesign::BandPass {
+                taps,
+                low,
+                high,
+                window,
+            } => bandpass(taps, low, high, window),
+            FirDesign::BandStop {
+                taps,
+                low,
+                high,
+                window,
+            } => bandstop(taps, low, high, window),
+            FirDesign::MovingAverage(n) => moving_average(n),
+            FirDesign::Derivative { order, points } => derivative(order, points),
+        }
+    }
+
+    /// label used in output names
+    pub fn label(&self) -> String {
+        match *self {
+            FirDesign::LowPass { taps, cutoff, .. } => format!("lowpass-{}-{}", taps, cutoff),
+            FirDesign::HighPass { taps, cutoff, .. } => format!("highpass-{}-{}", taps, cutoff),
+            FirDesign::BandPass {
+                taps, low, high, ..
+            } => format!("bandpass-{}-{}-{}", taps, low, high),
+            FirDesign::BandStop {
+                taps, low, high, ..
+            } => format!("bandstop-{}-{}-{}", taps, low, high),
+            FirDesign::MovingAverage(n) => format!("average-{}", n),
+            FirDesign::Derivative { order, points } => format!("derivative-{}-{}", order, points),
+        }
+    }
+}
+
+/// Windowed-sinc low-pass, scaled so the gain at 0 is exactly 1.
+pub fn lowpass(taps: usize, cutoff: f64, window: WindowFunction) -> Result<Vec<f64>> {
+    check_taps(taps)?;
+    check_cutoff(cutoff)?;
+
+    let middle = (taps / 2) as f64;
+    let w = window.symmetric(taps);
+    let h = (0..taps)
+        .map(|i| {
+            let t = i as f64 - middle;
+            let sinc = if t == 0. {
+                2. * cutoff
+            } else {
+                (2. * PI * cutoff * t).sin() / (PI * t)
+            };
+            sinc * w[i]
+        })
+        .collect::<Vec<f64>>();
+
+    let sum = h.iter().sum::<f64>();
+    Ok(h.iter().map(|v| v / sum).collect())
+}
+
+/// Windowed-sinc high-pass, the low-pass subtracted from a unit impulse.
+pub fn highpass(taps: usize, cutoff: f64, window: WindowFunction) -> Result<Vec<f64>> {
+    let mut h = lowpass(taps, cutoff, window)?
+        .iter()
+        .map(|v| -v)
+        .collect::<Vec<f64>>();
+    h[taps / 2] += 1.;
+    Ok(h)
+}
+
+/// Windowed-sinc band-pass keeping `low` to `high`, the difference of two
+/// low-passes.
+pub fn bandpass(taps: usize, low: f64, high: f64, window: WindowFunction) -> Result<Vec<f64>> {
+    check_band(low, high)?;
+    let wide = lowpass(taps, high, window)?;
+    let narrow = lowpass(taps, low, window)?;
+    Ok(wide.iter().zip(narrow.iter()).map(|(a, b)| a - b).collect())
+}
+
+/// Windowed-sinc band-stop removing `low` to `high`, the band-pass
+/// subtracted from a unit impulse.
+pub fn bandstop(taps: usize, low: f64, high: f64, window: WindowFunction) -> Result<Vec<f64>> {
+    let mut h = bandpass(taps, low, high, window)?
+        .iter()
+        .map(|v| -v)
+        .collect::<Vec<f64>>();
+    h[taps / 2] += 1.;
+    Ok(h)
+}
+
+/// `n` equal taps summing to one
+pub fn moving_average(n: usize) -> Result<Vec<f64>> {
+    if n == 0 {
+        return Err(Error::Kernel(
+            "a moving average needs at least 1 tap".to_string(),
+        ));
+    }
+    Ok(vec![1. / n as f64; n])
+}
+
+/// Central difference for the first or second derivative over 3 or 5
+/// points, in convolution order so `conv1d::convolve` in `Same` mode
+/// gives the derivative at each sample.
+pub fn derivative(order: usize, points: usize) -> Result<Vec<f64>> {
+    let taps = match (order, points) {
+        (1, 3) => vec![0.5, 0., -0.5],
+        (1, 5) => vec![-1., 8., 0., -8., 1.]
+            .into_iter()
+            .map(|v| v / 12.)
+            .collect(),
+        (2, 3) => vec![1., -2., 1.],
+        (2, 5) => vec![-1., 16., -30., 16., -1.]
+            .into_iter()
+            .map(|v| v / 12.)
+            .collect(),
+        _ => {
+            return Err(Error::Kernel(format!(
+                "no {} point derivative of order {}, expected order 1 or 2 over 3 or 5 points",
+                points, order
+            )))
+        }
+    };
+    Ok(taps)
+}
+
+/// Response of a kernel at `points + 1` frequencies evenly spaced from 0
+/// to 0.5 cycles per sample, value `i` at `i / (2 * points)`, from an fft
+/// of `2 * points` samples. Take `norm()` for the gain and `arg()` for the
+/// phase.
+pub fn frequency_response(kernel: &[f64], points: usize) -> Vec<Complex<f64>> {
+    let size = 2 * points.max(1);
+
+    // a kernel longer than the fft is wrapped around, which samples its
+    // transform at the same frequencies
+    let mut h = vec![Complex::zero(); size];
+    for (n, v) in kernel.iter().enumerate() {
+        h[n % size].re += v;
+    }
+
+    let mut planner = FftPlanner::new();
+    planner.plan_fft_forward(size).process(&mut h);
+
+    h.truncate(size / 2 + 1);
+    h
+}
+
+/// the gain of a response in decibels, floored at -300
+pub fn magnitude_db(response: &[Complex<f64>]) -> Vec<f64> {
+    response
+        .iter()
+        .map(|v| 20. * v.norm().max(1e-15).log10())
+        .collect()
+}
+
+fn check_taps(taps: usize) -> Result<()> {
+    if taps.is_multiple_of(2) {
+        return Err(Error::Kernel(format!(
+            "windowed-sinc filters need an odd number of taps, not {}",
+            taps
+        )));
+    }
+    Ok(())
+}
+
+fn check_cutoff(cutoff: f64) -> Result<()> {
+    if !(cutoff > 0. && cutoff < 0.5) {
+        return Err(Error::Kernel(format!(
+            "cutoff {} must be between 0 and 0.5 cycles per sample",
+            cutoff
+        )));
+    }
+    Ok(())
+}
+
+fn check_band(low: f64, high: f64) -> Result<()> {
+    if low >= high {
+        return Err(Error::Kernel(format!(
+            "the band {} to {} must have its low edge first",
+            low, high
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conv1d::{self, Method, OutputMode};
+    use crate::padding::Padding;
+
+    /// gain at `i / 1000` cycles per sample for i in 0..=500
+    fn gains(kernel: &[f64]) -> Vec<f64> {
+        frequency_response(kernel, 500)
+            .iter()
+            .map(|v| v.norm())
+            .collect()
+    }
+
+    #[test]
+    fn lowpass_passes_dc_and_stops_the_band() {
+        let windows = [
+            WindowFunction::Hamming,
+            WindowFunction::Blackman,
+            WindowFunction::Kaiser(8.),
+        ];
+        for window in windows {
+            let h = lowpass(101, 0.1, window).unwrap();
+            assert!((h.iter().sum::<f64>() - 1.).abs() < 1e-12);
+
+            let g = gains(&h);
+            assert!((g[0] - 1.).abs() < 1e-12);
+            assert!(
+                g[..=50].iter().all(|v| (v - 1.).abs() < 0.01),
+                "{:?}",
+                window
+            );
+            // above the transition band every window is at least 50 dB down
+            let stop = magnitude_db(&frequency_response(&h, 500));
+            assert!(stop[150..].iter().all(|db| *db < -50.), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn highpass_and_band_filters_keep_their_bands() {
+        let window = WindowFunction::Blackman;
+        let g = gains(&highpass(101, 0.2, window).unwrap());
+        assert!(g[0] < 1e-3);
+        assert!(g[300..].iter().all(|v| (v - 1.).abs() < 0.01));
+
+        let g = gains(&bandpass(151, 0.1, 0.3, window).unwrap());
+        assert!(g[..50].iter().all(|v| *v < 0.01));
+        assert!(g[150..=250].iter().all(|v| (v - 1.).abs() < 0.01));
+        assert!(g[350..].iter().all(|v| *v < 0.01));
+
+        let g = gains(&bandstop(151, 0.1, 0.3, window).unwrap());
+        assert!((g[0] - 1.).abs() < 0.01);
+        assert!(g[150..=250].iter().all(|v| *v < 0.01));
+    }
+
+    #[test]
+    fn derivatives_of_polynomials() {
+        let ramp = (0..20).map(|i| 3. * i as f64 + 1.).collect::<Vec<f64>>();
+        let square = (0..20).map(|i| (i * i) as f64).collect::<Vec<f64>>();
+        let filter = |x: &[f64], order, points| {
+            let taps = derivative(order, points).unwrap();
+            let y = conv1d::convolve(x, &taps, OutputMode::Same, Padding::Zero, Method::Direct);
+            // the ends see the zero padding
+            y[2..y.len() - 2].to_vec()
+        };
+
+        for points in [3, 5] {
+            for v in filter(&ramp, 1, points) {
+                assert!((v - 3.).abs() < 1e-12);
+            }
+            for v in filter(&ramp, 2, points) {
+                assert!(v.abs() < 1e-12);
+            }
+            for v in filter(&square, 2, points) {
+                assert!((v - 2.).abs() < 1e-12);
+            }
+        }
+        assert!(derivative(3, 3).is_err());
+        assert!(derivative(1, 4).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_designs() {
+        let window = WindowFunction::Hamming;
+        assert!(lowpass(10, 0.1, window).is_err());
+        assert!(lowpass(11, 0., window).is_err());
+        assert!(lowpass(11, 0.5, window).is_err());
+        assert!(lowpass(11, f64::NAN, window).is_err());
+        assert!(bandpass(11, 0.3, 0.1, window).is_err());
+        assert!(moving_average(0).is_err());
+        assert!("lowpass:11".parse::<FirDesign>().is_err());
+        assert!("lowpass:11:0.1:parzen".parse::<FirDesign>().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Filtering in the frequency domain
+//!
+//! A filter is a mask of gains over the 2d spectrum, multiplied with the
+//! fft of the image before transforming back. Frequencies are in cycles
+//! per pixel, from 0 up to 0.5 at the Nyquist limit, and the distance of
+//! a frequency from zero is measured radially.
+//!
+//! A gaussian low-pass with cutoff `c` matches a spatial gaussian kernel
+//! with `sigma = 1 / (2 pi c)`.
+
+use crate::dft;
+use crate::padding::{self, Padding};
+use num::complex::Complex;
+use std::str::FromStr;
+
+/// highest butterworth order, which is already all but a hard step
+const MAX_ORDER: u32 = 1000;
+
+/// how the gain falls off around a cutoff
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    /// a hard step, which rings in the spatial domain
+    Ideal,
+    /// `1 / (1 + (d / c)^(2 n))` for order `n`
+    Butterworth(u32),
+    /// `exp(-d^2 / (2 c^2))`, which does not ring
+    Gaussian,
+}
+
+/// which frequencies are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Passband {
+    /// keep frequencies below the cutoff
+    LowPass(f64),
+    /// keep frequencies above the cutoff
+    HighPass(f64),
+    /// keep frequencies between the two cutoffs
+    BandPass(f64, f64),
+    /// remove the frequency `(u, v)` and its mirror `(-u, -v)`, out to
+    /// `radius`. `u` is horizontal and `v` vertical.
+    Notch { u: f64, v: f64, radius: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyFilter {
+    pub band: Passband,
+    pub response: Response,
+}
+
+/// Parses `ideal`, `gaussian` and `butterworth[:<order>]`, the order
+/// defaulting to 2 and at most 1000.
+impl FromStr for Response {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<&str>>().as_slice() {
+            ["ideal"] => Ok(Response::Ideal),
+            ["gaussian"] => Ok(Response::Gaussian),
+            ["butterworth"] => Ok(Response::Butterworth(2)),
+            ["butterworth", order] => match order.parse::<u32>() {
+                Ok(order) if (1..=MAX_ORDER).contains(&order) => Ok(Response::Butterworth(order)),
+                _ => Err(format!(
+                    "'{}' is not an order from 1 to {}",
+                    order, MAX_ORDER
+                )),
+            },
+            _ => Err(format!(
+                "unknown response '{}', expected ideal, gaussian or butterworth:<order>",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses `lowpass:<cutoff>`, `highpass:<cutoff>`, `bandpass:<low>:<high>`
+/// and `notch:<u>:<v>:<radius>`, in cycles per pixel.
+impl FromStr for Passband {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<&str>>();
+        let numbers = parts[1..]
+            .iter()
+            .map(|v| {
+                v.parse::<f64>()
+                    .map_err(|_| format!("'{}' is not a number", v))
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+        let positive = |v: f64| {
+            if v > 0. {
+                Ok(v)
+            } else {
+                Err("cutoffs must be positive".to_string())
+            }
+        };
+
+        match (parts[0], numbers.as_slice()) {
+            ("lowpass", [c]) => Ok(Passband::LowPass(positive(*c)?)),
+            ("highpass", [c]) => Ok(Passband::HighPass(positive(*c)?)),
+            ("bandpass", [low, high]) => {
+                if low >= high {
+                    return Err("the low cutoff must be below the high one".to_string());
+                }
+                Ok(Passband::BandPass(positive(*low)?, positive(*high)?))
+            }
+            ("notch", [u, v, radius]) => Ok(Passband::Notch {
+                u: *u,
+                v: *v,
+                radius: positive(*radius)?,
+            }),
+            _ => Err(format!(
+                "unknown band '{}', expected lowpass:<cutoff>, highpass:<cutoff>, bandpass:<low>:<high> or notch:<u>:<v>:<radius>",
+                s
+            )),
+        }
+    }
+}
+
+impl FrequencyFilter {
+    pub fn new(band: Passband, response: Response) -> Self {
+        FrequencyFilter { band, response }
+    }
+
+    /// gain for the frequency `(u, v)` in cycles per pixel
+    pub fn gain(&self, u: f64, v: f64) -> f64 {
+        let d = (u * u + v * v).sqrt();
+        match self.band {
+            Passband::LowPass(c) => self.low(d, c),
+            Passband::HighPass(c) => 1. - self.low(d, c),
+            Passband::BandPass(low, high) => self.low(d, high) * (1. - self.low(d, low)),
+            Passband::Notch {
+                u: nu,
+                v: nv,
+                radius,
+            } => {
+                let d1 = ((u - nu).powi(2) + (v - nv).powi(2)).sqrt();
+                let d2 = ((u + nu).powi(2) + (v + nv).powi(2)).sqrt();
+                (1. - self.low(d1, radius)) * (1. - self.low(d2, radius))
+            }
+        }
+    }
+
+    /// low-pass gain at distance `d` for cutoff `c`
+    fn low(&self, d: f64, c: f64) -> f64 {
+        match self.response {
+            Response::Ideal => {
+                if d <= c {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Response::Butterworth(n) => 1. / (1. + (d / c).powf(2. * n as f64)),
+            Response::Gaussian => (-d * d / (2. * c * c)).exp(),
+        }
+    }
+
+    /// Gains for a `rows` by `cols` spectrum laid out as the fft returns
+    /// it, with zero frequency in the top left corner. Use
+    /// `spectrum::fftshift` to view it centred.
+    pub fn mask(&self, rows: usize, cols: usize) -> Vec<Vec<f64>> {
+        (0..rows)
+            .map(|i| {
+                let v = frequency(i, rows);
+                (0..cols)
+                    .map(|j| self.gain(frequency(j, cols), v))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Filter an image. It is padded by half its size on every side with
+    /// `mode` so the circular fft does not wrap one edge onto the other,
+    /// and cropped back afterwards.
+    pub fn apply(&self, input: &[Vec<f64>], mode: Padding) -> Vec<Vec<f64>> {
+        let (rows, cols) = (input.len(), input[0].len());
+        let (py, px) = (rows / 2, cols / 2);
+        let padded = padding::pad_sides(input, py, py, px, px, mode);
+
+        let image = padded
+            .iter()
+            .map(|row| row.iter().map(|v| Complex::new(*v, 0.0)).collect())
+            .collect::<Vec<Vec<Complex<f64>>>>();
+        let mut spectrum = dft::fft_2d(&image);
+
+        let mask = self.mask(spectrum.len(), spectrum[0].len());
+        for (row, gains) in spectrum.iter_mut().zip(mask.iter()) {
+            for (v, g) in row.iter_mut().zip(gains.iter()) {
+                *v *= g;
+            }
+        }
+
+        let result = dft::ifft_2d(&spectrum);
+        (0..rows)
+            .map(|i| (0..cols).map(|j| result[i + py][j + px].re).collect())
+            .collect()
+    }
+}
+
+/// signed frequency of fft bin `k` out of `n`, in cycles per pixel
+fn frequency(k: usize, n: usize) -> f64 {
+    let k = if k > n / 2 {
+        k as f64 - n as f64
+    } else {
+        k as f64
+    };
+    k / n as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolve;
+    use crate::kernel::{Kernel, KernelConstructors};
+
+    #[test]
+    fn butterworth_order_is_bounded() {
+        assert_eq!(
+            "butterworth:1000".parse::<Response>(),
+            Ok(Response::Butterworth(1000))
+        );
+        for s in [
+            "butterworth:0",
+            "butterworth:1001",
+            "butterworth:3000000000",
+        ] {
+            assert!(s.parse::<Response>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn gaussian_low_pass_matches_the_spatial_gaussian() {
+        let img = (0..40)
+            .map(|i| {
+                (0..36)
+                    .map(|j| ((i * 7 + j * 3) % 11) as f64 / 10.)
+                    .collect()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        let cutoff = 0.08;
+        let sigma = 1. / (2. * std::f64::consts::PI * cutoff);
+
+        let filtered = FrequencyFilter::new(Passband::LowPass(cutoff), Response::Gaussian)
+            .apply(&img, Padding::Reflection);
+        let k = Kernel::gaussian(17, sigma).unwrap();
+        let blurred = convolve::convolve_2d(&img, &k, Padding::Reflection);
+
+        // the two pad differently, so only the middle is compared
+        for i in 10..30 {
+            for j in 10..26 {
+                let d = (filtered[i][j] - blurred[i][j]).abs();
+                assert!(d < 1e-3, "{} at ({}, {})", d, i, j);
+            }
+        }
+    }
+}
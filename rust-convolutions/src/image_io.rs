@@ -0,0 +1,503 @@
+//! Reading and writing grayscale images
+//!
+//! Results can be written as 8 or 16 bit integers, or as 32 bit floats
+//! (TIFF, OpenEXR or PFM) so they can be read back by a later run without
+//! being quantized or clipped to 0-1.
+
+use crate::color::{Alpha, ColorOptions};
+use crate::mapping::{self, ValueMap};
+use crate::prelude::*;
+use image::{ColorType, DynamicImage, ImageBuffer, Luma, LumaA, Rgb32FImage, Rgba32FImage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{colortype, TiffEncoder};
+
+/// width, height and grey values of an image that has been read
+type Loaded = (u32, u32, Vec<Vec<f64>>);
+
+/// the alpha channel of an image that has one, 0-1
+type AlphaPlane = Option<Vec<Vec<f64>>>;
+
+/// How output values are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    /// 0-255, values are clipped to 0-1
+    Eight,
+    /// 0-65535, values are clipped to 0-1 (png and tiff)
+    Sixteen,
+    /// 32 bit floats kept as they are (tiff, exr and pfm)
+    Float,
+}
+
+impl BitDepth {
+    /// float for `.exr` and `.pfm`, which only hold floats, 8 bit otherwise
+    pub fn for_path(path: impl AsRef<Path>) -> BitDepth {
+        match extension(path.as_ref()).as_str() {
+            "exr" | "pfm" => BitDepth::Float,
+            _ => BitDepth::Eight,
+        }
+    }
+}
+
+/// Parses `8`, `16` and `float` (or `32`).
+impl FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            "32" | "float" => Ok(BitDepth::Float),
+            _ => Err(format!(
+                "unknown bit depth '{}', expected 8, 16 or float",
+                s
+            )),
+        }
+    }
+}
+
+/// whether `path` has an extension that `read_image` can open
+pub fn is_image_path(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    extension(path) == "pfm" || image::ImageFormat::from_path(path).is_ok()
+}
+
+/// Read an image as a grid of grey values, returning its dimensions.
+/// Integer images are scaled to 0-1 at their full precision, float
+/// images (tiff, exr and pfm) keep their values. `color` picks how colour
+/// and alpha become grey.
+pub fn read_image(path: impl AsRef<Path>, color: &ColorOptions) -> Result<Loaded> {
+    read_image_alpha(path, color).map(|(loaded, _)| loaded)
+}
+
+/// `read_image`, also returning the alpha channel if the image has one so
+/// it can be given back to `save_img`.
+pub fn read_image_alpha(
+    path: impl AsRef<Path>,
+    color: &ColorOptions,
+) -> Result<(Loaded, AlphaPlane)> {
+    let path = path.as_ref();
+
+    match extension(path).as_str() {
+        "pfm" => return read_pfm(path, color).map(|img| (img, None)),
+        "tif" | "tiff" => {
+            // the image crate only decodes integer tiffs
+            if let Some(img) = read_float_tiff(path, color)? {
+                return Ok(img);
+            }
+        }
+        _ => {}
+    }
+
+    let img = image::open(path)?;
+    let encoded = !matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+    let has_alpha = img.color().has_alpha();
+
+    // to_rgba32f scales 8 and 16 bit values to 0-1 and leaves floats alone
+    let img = img.to_rgba32f();
+    let (width, height) = img.dimensions();
+
+    // create an array to hold the image
+    let mut gray_img = vec![vec![0.; width as usize]; height as usize];
+    let mut alpha = vec![vec![1.; width as usize]; height as usize];
+
+    for (x, y, pixel) in img.enumerate_pixels() {
+        gray_img[y as usize][x as usize] = color.gray_of(pixel.0, encoded);
+        alpha[y as usize][x as usize] = pixel.0[3] as f64;
+    }
+
+    Ok(((width, height, gray_img), has_alpha.then_some(alpha)))
+}
+
+/// Save a grid of grey values, the format comes from the extension. The
+/// `maps` are applied in order first. Integer depths encode the values
+/// for `color` and clip whatever is still outside 0-1, float depths write
+/// the values as they are.
+///
+/// With `Alpha::Premultiply` and the `alpha` read with the input, the
+/// values are divided by alpha again and the alpha is written back, which
+/// needs a png, tif or exr. An `alpha` of another size is left out, as is
+/// any alpha for `drop` and `composite`, whose output is opaque.
+pub fn save_img(
+    path: impl AsRef<Path>,
+    img: &[Vec<f64>],
+    maps: &[ValueMap],
+    depth: BitDepth,
+    color: &ColorOptions,
+    alpha: Option<&[Vec<f64>]>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let (width, height) = (
+        img.first().map_or(0, |row| row.len()) as u32,
+        img.len() as u32,
+    );
+    let mut img = mapping::apply_all(img, maps);
+    let ext = extension(path);
+
+    let alpha = alpha.filter(|a| {
+        color.alpha == Alpha::Premultiply
+            && a.len() == height as usize
+            && a.iter().all(|row| row.len() == width as usize)
+    });
+    if let Some(alpha) = alpha {
+        if !matches!(ext.as_str(), "png" | "tif" | "tiff" | "exr") {
+            return Err(Error::Generic(format!(
+                "output with alpha needs a .png, .tif or .exr file, not '{}'",
+                path.display()
+            )));
+        }
+        // fully transparent pixels have no value to recover
+        for (row, a_row) in img.iter_mut().zip(alpha.iter()) {
+            for (v, a) in row.iter_mut().zip(a_row.iter()) {
+                *v = if *a > 0. { *v / a } else { 0. };
+            }
+        }
+    }
+
+    let level = |x: u32, y: u32| color.encode(img[y as usize][x as usize]).clamp(0.0, 1.0);
+    let opacity = |x: u32, y: u32| alpha.map_or(1., |a| a[y as usize][x as usize].clamp(0., 1.));
+
+    match depth {
+        BitDepth::Eight => {
+            if alpha.is_some() {
+                let ouput = ImageBuffer::from_fn(width, height, |x, y| {
+                    LumaA([
+                        (level(x, y) * 255.0).round() as u8,
+                        (opacity(x, y) * 255.0).round() as u8,
+                    ])
+                });
+                DynamicImage::ImageLumaA8(ouput).to_rgba8().save(path)?;
+            } else {
+                let ouput = ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([(level(x, y) * 255.0).round() as u8])
+                });
+                ouput.save(path)?;
+            }
+        }
+        BitDepth::Sixteen => {
+            if !matches!(ext.as_str(), "png" | "tif" | "tiff") {
+                return Err(Error::Generic(format!(
+                    "16 bit output needs a .png or .tif file, not '{}'",
+                    path.display()
+                )));
+            }
+            if alpha.is_some() {
+                let ouput = ImageBuffer::from_fn(width, height, |x, y| {
+                    LumaA([
+                        (level(x, y) * 65535.0).round() as u16,
+                        (opacity(x, y) * 65535.0).round() as u16,
+                    ])
+                });
+                DynamicImage::ImageLumaA16(ouput).to_rgba16().save(path)?;
+            } else {
+                let ouput = ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([(level(x, y) * 65535.0).round() as u16])
+                });
+                ouput.save(path)?;
+            }
+        }
+        BitDepth::Float => match ext.as_str() {
+            "pfm" => write_pfm(width, height, path, &img)?,
+            "tif" | "tiff" => write_float_tiff(width, height, path, &img, alpha)?,
+            "exr" => {
+                // the exr encoder wants rgb, so the grey is repeated
+                if alpha.is_some() {
+                    let ouput: Rgba32FImage = ImageBuffer::from_fn(width, height, |x, y| {
+                        let v = img[y as usize][x as usize] as f32;
+                        image::Rgba([v, v, v, opacity(x, y) as f32])
+                    });
+                    DynamicImage::ImageRgba32F(ouput).save(path)?;
+                } else {
+                    let ouput: Rgb32FImage = ImageBuffer::from_fn(width, height, |x, y| {
+                        let v = img[y as usize][x as usize] as f32;
+                        image::Rgb([v, v, v])
+                    });
+                    DynamicImage::ImageRgb32F(ouput).save(path)?;
+                }
+            }
+            _ => {
+                return Err(Error::Generic(format!(
+                    "float output needs a .tif, .exr or .pfm file, not '{}'",
+                    path.display()
+                )))
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// widen a grey, grey and alpha, rgb or rgba pixel to rgba
+fn rgba(p: &[f32], channels: usize) -> [f32; 4] {
+    match channels {
+        1 => [p[0], p[0], p[0], 1.],
+        2 => [p[0], p[0], p[0], p[1]],
+        3 => [p[0], p[1], p[2], 1.],
+        _ => [p[0], p[1], p[2], p[3]],
+    }
+}
+
+/// lowercase extension of `path`, empty if it has none
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Read a tiff of 32 bit floats with up to four channels, two and four
+/// having alpha. Returns `None` for integer tiffs so they go through the
+/// image crate.
+fn read_float_tiff(path: &Path, color: &ColorOptions) -> Result<Option<(Loaded, AlphaPlane)>> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    let (width, height) = decoder.dimensions()?;
+
+    let values = match decoder.read_image()? {
+        DecodingResult::F32(values) => values,
+        _ => return Ok(None),
+    };
+
+    let pixels = (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(|| Error::Generic(format!("'{}' is too large to read", path.display())))?;
+    let channels = values.len() / pixels.max(1);
+    if !(1..=4).contains(&channels) || values.len() != pixels * channels {
+        return Err(Error::Generic(format!(
+            "'{}' has an unsupported float layout",
+            path.display()
+        )));
+    }
+
+    let pixel = |x: usize, y: usize| rgba(&values[(y * width as usize + x) * channels..], channels);
+    let plane = |f: &dyn Fn([f32; 4]) -> f64| -> Vec<Vec<f64>> {
+        (0..height as usize)
+            .map(|y| (0..width as usize).map(|x| f(pixel(x, y))).collect())
+            .collect()
+    };
+
+    let gray_img = plane(&|p| color.gray_of(p, false));
+    let alpha = (channels == 2 || channels == 4).then(|| plane(&|p| p[3] as f64));
+
+    Ok(Some(((width, height, gray_img), alpha)))
+}
+
+/// write grey, or grey and alpha, as 32 bit floats
+fn write_float_tiff(
+    width: u32,
+    height: u32,
+    path: &Path,
+    img: &[Vec<f64>],
+    alpha: Option<&[Vec<f64>]>,
+) -> Result<()> {
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+
+    match alpha {
+        // tiff has no grey and alpha float layout, so the grey is repeated
+        Some(alpha) => {
+            let values = img
+                .iter()
+                .flatten()
+                .zip(alpha.iter().flatten())
+                .flat_map(|(v, a)| [*v as f32, *v as f32, *v as f32, *a as f32])
+                .collect::<Vec<f32>>();
+            encoder.write_image::<colortype::RGBA32Float>(width, height, &values)?;
+        }
+        None => {
+            let values = img
+                .iter()
+                .flatten()
+                .map(|v| *v as f32)
+                .collect::<Vec<f32>>();
+            encoder.write_image::<colortype::Gray32Float>(width, height, &values)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a portable float map, `Pf` (grey) or `PF` (rgb). Rows are stored
+/// bottom to top and a negative scale means little endian.
+fn read_pfm(path: &Path, color: &ColorOptions) -> Result<Loaded> {
+    let mut bytes = vec![];
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+    let bad =
+        |msg: &str| Error::Generic(format!("'{}' is not a valid pfm: {}", path.display(), msg));
+
+    // the header is four whitespace separated tokens followed by a single
+    // whitespace character
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while tokens.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(bad("header is incomplete"));
+        }
+        tokens.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+    }
+    pos += 1;
+
+    let channels = match tokens[0].as_str() {
+        "Pf" => 1,
+        "PF" => 3,
+        _ => return Err(bad("expected Pf or PF")),
+    };
+    let width = tokens[1].parse::<u32>().map_err(|_| bad("bad width"))?;
+    let height = tokens[2].parse::<u32>().map_err(|_| bad("bad height"))?;
+    let scale = tokens[3].parse::<f64>().map_err(|_| bad("bad scale"))?;
+    if width == 0 || height == 0 {
+        return Err(bad("width and height must be at least 1"));
+    }
+
+    // a header can claim more pixels than fit in memory
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .ok_or_else(|| bad("image is too large"))?;
+    if bytes.len() < pos.saturating_add(size) {
+        return Err(bad("data is truncated"));
+    }
+
+    let values = bytes[pos..pos + size]
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if scale < 0. {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect::<Vec<f32>>();
+
+    let mut gray_img = vec![vec![0.; width as usize]; height as usize];
+    for (i, row) in values.chunks_exact(width as usize * channels).enumerate() {
+        let y = height as usize - 1 - i;
+        for x in 0..width as usize {
+            let p = &row[x * channels..];
+            gray_img[y][x] = color.gray_of(rgba(p, channels), false);
+        }
+    }
+
+    Ok((width, height, gray_img))
+}
+
+/// write a little endian grey `Pf` float map
+fn write_pfm(width: u32, height: u32, path: &Path, img: &[Vec<f64>]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "Pf\n{} {}\n-1.0\n", width, height)?;
+
+    for row in img.iter().rev() {
+        for v in row {
+            out.write_all(&(*v as f32).to_le_bytes())?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pfm_rejects_zero_dimensions() {
+        let path = std::env::temp_dir().join("convolutions-empty.pfm");
+        for header in ["Pf\n0 2\n-1.0\n", "Pf\n2 0\n-1.0\n"] {
+            std::fs::write(&path, header).unwrap();
+            assert!(read_image(&path, &ColorOptions::default()).is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pfm_rejects_huge_dimensions() {
+        let path = std::env::temp_dir().join("convolutions-huge.pfm");
+        std::fs::write(&path, "Pf\n4294967295 4294967295\n-1.0\n").unwrap();
+        assert!(read_image(&path, &ColorOptions::default()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn image() -> Vec<Vec<f64>> {
+        (0..5)
+            .map(|i| {
+                (0..7)
+                    .map(|j| ((i * 7 + j * 3) % 11) as f64 / 10.)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// save `img` and read it back
+    fn round_trip(
+        name: &str,
+        img: &[Vec<f64>],
+        depth: BitDepth,
+        color: &ColorOptions,
+        alpha: Option<&[Vec<f64>]>,
+    ) -> (Vec<Vec<f64>>, AlphaPlane) {
+        let path = std::env::temp_dir().join(format!("convolutions-round-trip-{}", name));
+        save_img(&path, img, &[], depth, color, alpha).unwrap();
+        let ((width, height, read), alpha) = read_image_alpha(&path, color).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((width as usize, height as usize), (img[0].len(), img.len()));
+        (read, alpha)
+    }
+
+    fn assert_close(a: &[Vec<f64>], b: &[Vec<f64>], tolerance: f64) {
+        for (x, y) in a.iter().flatten().zip(b.iter().flatten()) {
+            assert!((x - y).abs() <= tolerance, "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_png_keeps_its_precision() {
+        let img = image()
+            .iter()
+            .map(|row| row.iter().map(|v| v * 0.9 + 1e-4).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let color = ColorOptions::default();
+        let (read, alpha) = round_trip("16.png", &img, BitDepth::Sixteen, &color, None);
+        assert!(alpha.is_none());
+        assert_close(&read, &img, 0.5 / 65535. + 1e-9);
+    }
+
+    #[test]
+    fn float_formats_keep_values_outside_0_1() {
+        let img = image()
+            .iter()
+            .map(|row| row.iter().map(|v| v * 40. - 7.5).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let color = ColorOptions::default();
+        for name in ["float.tif", "float.exr", "float.pfm"] {
+            let (read, alpha) = round_trip(name, &img, BitDepth::Float, &color, None);
+            assert!(alpha.is_none());
+            assert_close(&read, &img, 1e-5);
+        }
+    }
+
+    #[test]
+    fn float_tiff_and_exr_keep_the_alpha() {
+        let img = image();
+        let alpha = image()
+            .iter()
+            .map(|row| row.iter().map(|v| 1. - v / 2.).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let color = ColorOptions {
+            alpha: Alpha::Premultiply,
+            ..ColorOptions::default()
+        };
+        for name in ["alpha.tif", "alpha.exr"] {
+            let (_, read) = round_trip(name, &img, BitDepth::Float, &color, Some(&alpha));
+            assert_close(&read.unwrap(), &alpha, 1e-6);
+        }
+    }
+}
@@ -1,83 +1,319 @@
-pub type Kernel = Vec<Vec<f64>>;
+use crate::prelude::*;
+use std::ops::Index;
 
-pub trait KernelConstructors {
+/// A rectangular, non-empty matrix of filter weights. The anchor is the
+/// weight placed over the output pixel, and defaults to the center.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    values: Vec<Vec<f64>>,
+    anchor: (usize, usize),
+}
+
+impl Kernel {
+    /// Validate the rows of a kernel, anchoring it at the center. Every
+    /// weight must be finite.
+    pub fn new(values: Vec<Vec<f64>>) -> Result<Self> {
+        if values.is_empty() || values[0].is_empty() {
+            return Err(Error::Kernel("kernel must not be empty".to_string()));
+        }
+        if values.iter().any(|row| row.len() != values[0].len()) {
+            return Err(Error::Kernel(
+                "kernel rows must all be the same length".to_string(),
+            ));
+        }
+        if values.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(Error::Kernel("kernel weights must be finite".to_string()));
+        }
+
+        let anchor = (values.len() / 2, values[0].len() / 2);
+        Ok(Kernel { values, anchor })
+    }
+
+    /// Move the anchor to `(row, col)`.
+    pub fn with_anchor(mut self, row: usize, col: usize) -> Result<Self> {
+        if row >= self.rows() || col >= self.cols() {
+            return Err(Error::Kernel(format!(
+                "anchor ({}, {}) is outside the {}x{} kernel",
+                row,
+                col,
+                self.rows(),
+                self.cols()
+            )));
+        }
+
+        self.anchor = (row, col);
+        Ok(self)
+    }
+
+    /// wrap the literal rows of the fixed kernels, which are never empty
+    /// or ragged
+    fn from_rows(values: Vec<Vec<f64>>) -> Self {
+        match Kernel::new(values) {
+            Ok(kernel) => kernel,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.values[0].len()
+    }
+
+    /// (rows, cols)
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows(), self.cols())
+    }
+
+    /// (row, col) of the anchor
+    pub fn anchor(&self) -> (usize, usize) {
+        self.anchor
+    }
+
+    pub fn values(&self) -> &[Vec<f64>] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Vec<f64>> {
+        self.values
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.values.iter().flatten().sum()
+    }
+
+    /// Scale so the weights sum to one. Kernels that sum to zero, such as
+    /// edge detectors, are returned unchanged.
+    pub fn normalize(&self) -> Self {
+        let sum = self.sum();
+        if sum == 0.0 {
+            return self.clone();
+        }
+        self.scale(1.0 / sum)
+    }
+
+    /// multiply every weight by `factor`
+    pub fn scale(&self, factor: f64) -> Self {
+        let values = self
+            .values
+            .iter()
+            .map(|row| row.iter().map(|v| v * factor).collect())
+            .collect();
+        Kernel {
+            values,
+            anchor: self.anchor,
+        }
+    }
+
+    /// swap rows and columns
+    pub fn transpose(&self) -> Self {
+        let values = (0..self.cols())
+            .map(|j| (0..self.rows()).map(|i| self.values[i][j]).collect())
+            .collect();
+        Kernel {
+            values,
+            anchor: (self.anchor.1, self.anchor.0),
+        }
+    }
+
+    /// Rotate by 180 degrees. Correlating with the flipped kernel is the
+    /// same as convolving with the original.
+    pub fn flip(&self) -> Self {
+        self.rotate(2)
+    }
+
+    /// Rotate clockwise by `quarter_turns` multiples of 90 degrees.
+    pub fn rotate(&self, quarter_turns: usize) -> Self {
+        let mut kernel = self.clone();
+        for _ in 0..quarter_turns % 4 {
+            // new[i][j] = old[rows - 1 - j][i]
+            let (rows, cols) = kernel.shape();
+            let values = (0..cols)
+                .map(|i| (0..rows).map(|j| kernel.values[rows - 1 - j][i]).collect())
+                .collect();
+            let (row, col) = kernel.anchor;
+            kernel = Kernel {
+                values,
+                anchor: (col, rows - 1 - row),
+            };
+        }
+        kernel
+    }
+
+    /// Element-wise sum of two kernels of the same shape, keeping the
+    /// anchor of `self`.
+    pub fn try_add(&self, other: &Kernel) -> Result<Self> {
+        if self.shape() != other.shape() {
+            return Err(Error::Kernel(format!(
+                "cannot add a {}x{} kernel to a {}x{} kernel",
+                other.rows(),
+                other.cols(),
+                self.rows(),
+                self.cols()
+            )));
+        }
+
+        let values = self
+            .values
+            .iter()
+            .zip(other.values.iter())
+            .map(|(a, b)| a.iter().zip(b.iter()).map(|(x, y)| x + y).collect())
+            .collect();
+        Ok(Kernel {
+            values,
+            anchor: self.anchor,
+        })
+    }
+
+    /// Combine two kernels into one, so filtering with the result is the
+    /// same as filtering with `self` and then `other`. This is the full
+    /// convolution of the two kernels, and the anchors add.
+    pub fn compose(&self, other: &Kernel) -> Self {
+        let (rows, cols) = (
+            self.rows() + other.rows() - 1,
+            self.cols() + other.cols() - 1,
+        );
+        let mut values = vec![vec![0.0; cols]; rows];
+
+        for i in 0..self.rows() {
+            for j in 0..self.cols() {
+                for n in 0..other.rows() {
+                    for m in 0..other.cols() {
+                        values[i + n][j + m] += self.values[i][j] * other.values[n][m];
+                    }
+                }
+            }
+        }
+
+        Kernel {
+            values,
+            anchor: (
+                self.anchor.0 + other.anchor.0,
+                self.anchor.1 + other.anchor.1,
+            ),
+        }
+    }
+}
+
+/// `kernel[n][m]` reads a weight. There is deliberately no `Deref` to
+/// the rows, so a `Kernel` cannot be passed by mistake to the functions
+/// taking bare rows, which ignore the anchor.
+impl Index<usize> for Kernel {
+    type Output = Vec<f64>;
+
+    fn index(&self, row: usize) -> &Self::Output {
+        &self.values[row]
+    }
+}
+
+pub trait KernelConstructors: Sized {
     fn identity() -> Self;
     fn blur() -> Self;
-    fn gaussian(size: usize, sigma: f64) -> Self;
+    fn gaussian(size: usize, sigma: f64) -> Result<Self>;
     fn edge_x() -> Self;
     fn edge_y() -> Self;
     fn edge_all() -> Self;
     fn sharpen() -> Self;
     fn emboss() -> Self;
     fn edge_enhance() -> Self;
+    fn laplacian_of_gaussian(size: usize, sigma: f64) -> Result<Self>;
+    fn difference_of_gaussians(size: usize, sigma1: f64, sigma2: f64) -> Result<Self>;
+    fn gabor(
+        size: usize,
+        sigma: f64,
+        theta: f64,
+        wavelength: f64,
+        phase: f64,
+        gamma: f64,
+    ) -> Result<Self>;
+    fn scharr_x() -> Self;
+    fn scharr_y() -> Self;
+    fn prewitt_x() -> Self;
+    fn prewitt_y() -> Self;
+    fn roberts_x() -> Self;
+    fn roberts_y() -> Self;
+    fn motion_blur(size: usize, angle: f64) -> Result<Self>;
+    fn box_blur(size: usize) -> Result<Self>;
+    fn disk(radius: usize) -> Self;
+    fn unsharp_mask(size: usize, sigma: f64, amount: f64) -> Result<Self>;
 }
 
 impl KernelConstructors for Kernel {
     fn identity() -> Self {
-        vec![vec![0., 0., 0.], vec![0., 1., 0.], vec![0., 0., 0.]]
+        Self::from_rows(vec![vec![0., 0., 0.], vec![0., 1., 0.], vec![0., 0., 0.]])
     }
     fn blur() -> Self {
-        vec![
+        Self::from_rows(vec![
             vec![1.0 / 9.0, 1.0 / 9.0, 1.0 / 9.0],
             vec![1.0 / 9.0, 1.0 / 9.0, 1.0 / 9.0],
             vec![1.0 / 9.0, 1.0 / 9.0, 1.0 / 9.0],
-        ]
+        ])
     }
-    fn gaussian(size: usize, sigma: f64) -> Self {
+    fn gaussian(size: usize, sigma: f64) -> Result<Self> {
+        check_size("gaussian size", size)?;
+        check_positive("gaussian sigma", sigma)?;
         let center = (size / 2) as f64;
         let variance = sigma.powi(2);
 
         let mut kernel: Vec<Vec<f64>> = vec![vec![0.0; size]; size];
 
-        for i in 0..size {
-            for j in 0..size {
+        for (i, row) in kernel.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
                 let x = (i as f64) - center;
                 let y = (j as f64) - center;
 
                 let g = (1.0 / (2.0 * std::f64::consts::PI * variance))
-                    * (-1.0 * ((x.powi(2) + y.powi(2)) / (2.0 * variance))).exp();
-                kernel[i][j] = g;
+                    * (-((x.powi(2) + y.powi(2)) / (2.0 * variance))).exp();
+                *v = g;
             }
         }
 
         let sum: f64 = kernel.iter().flatten().sum();
-        for i in 0..size {
-            for j in 0..size {
-                kernel[i][j] /= sum;
-            }
+        for v in kernel.iter_mut().flatten() {
+            *v /= sum;
         }
 
-        kernel
+        Kernel::new(kernel)
     }
     fn edge_x() -> Self {
-        vec![
+        Self::from_rows(vec![
             vec![-1.0, 0.0, 1.0],
             vec![-2.0, 0.0, 2.0],
             vec![-1.0, 0.0, 1.0],
-        ]
+        ])
     }
     fn edge_y() -> Self {
-        vec![
+        Self::from_rows(vec![
             vec![-1.0, -2.0, -1.0],
             vec![0.0, 0.0, 0.0],
             vec![1.0, 2.0, 1.0],
-        ]
+        ])
     }
     fn edge_all() -> Self {
-        vec![
+        Self::from_rows(vec![
             vec![0.0, 1.0, 0.0],
             vec![1.0, -4.0, 1.0],
             vec![0.0, 1.0, 0.0],
-        ]
+        ])
     }
     fn sharpen() -> Self {
-        vec![vec![0., -1., 0.], vec![-1., 5., -1.], vec![0., -1., 0.]]
+        Self::from_rows(vec![
+            vec![0., -1., 0.],
+            vec![-1., 5., -1.],
+            vec![0., -1., 0.],
+        ])
     }
     fn emboss() -> Self {
-        vec![vec![-2., -1., 0.], vec![-1., 1., 1.], vec![0., 1., 2.]]
+        Self::from_rows(vec![
+            vec![-2., -1., 0.],
+            vec![-1., 1., 1.],
+            vec![0., 1., 2.],
+        ])
     }
     fn edge_enhance() -> Self {
-        vec![
+        Self::from_rows(vec![
             vec![-1., -1., -1., -1., -1., -1., -1.],
             vec![-1., -1., -1., -1., -1., -1., -1.],
             vec![-1., -1., -1., -1., -1., -1., -1.],
@@ -85,6 +321,377 @@ impl KernelConstructors for Kernel {
             vec![-1., -1., -1., -1., -1., -1., -1.],
             vec![-1., -1., -1., -1., -1., -1., -1.],
             vec![-1., -1., -1., -1., -1., -1., -1.],
-        ]
+        ])
+    }
+    /// Laplacian of a gaussian, shifted so the kernel sums to zero and
+    /// flat regions give no response.
+    fn laplacian_of_gaussian(size: usize, sigma: f64) -> Result<Self> {
+        check_size("laplacian of gaussian size", size)?;
+        check_positive("laplacian of gaussian sigma", sigma)?;
+        let center = (size / 2) as f64;
+        let variance = sigma.powi(2);
+
+        let mut kernel: Vec<Vec<f64>> = vec![vec![0.0; size]; size];
+
+        for (i, row) in kernel.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                let x = (i as f64) - center;
+                let y = (j as f64) - center;
+                let r = (x.powi(2) + y.powi(2)) / (2.0 * variance);
+
+                *v = -(1.0 / (std::f64::consts::PI * variance.powi(2))) * (1.0 - r) * (-r).exp();
+            }
+        }
+
+        let mean = kernel.iter().flatten().sum::<f64>() / (size * size) as f64;
+        for v in kernel.iter_mut().flatten() {
+            *v -= mean;
+        }
+
+        Kernel::new(kernel)
+    }
+    /// Difference of two normalized gaussians, a band-pass approximation
+    /// of the Laplacian of gaussian. Sums to zero.
+    fn difference_of_gaussians(size: usize, sigma1: f64, sigma2: f64) -> Result<Self> {
+        let g1 = Self::gaussian(size, sigma1)?;
+        let g2 = Self::gaussian(size, sigma2)?;
+
+        Kernel::new(
+            g1.values()
+                .iter()
+                .zip(g2.values().iter())
+                .map(|(r1, r2)| r1.iter().zip(r2.iter()).map(|(a, b)| a - b).collect())
+                .collect(),
+        )
+    }
+    /// Gabor filter: a gaussian envelope modulating a sinusoid.
+    /// `theta` is the orientation in radians, `wavelength` the period of the
+    /// sinusoid in pixels, `phase` its offset in radians and `gamma` the
+    /// aspect ratio of the envelope.
+    fn gabor(
+        size: usize,
+        sigma: f64,
+        theta: f64,
+        wavelength: f64,
+        phase: f64,
+        gamma: f64,
+    ) -> Result<Self> {
+        check_size("gabor size", size)?;
+        check_positive("gabor sigma", sigma)?;
+        check_positive("gabor wavelength", wavelength)?;
+        let center = (size / 2) as f64;
+        let (sin, cos) = theta.sin_cos();
+
+        let mut kernel: Vec<Vec<f64>> = vec![vec![0.0; size]; size];
+
+        for (i, row) in kernel.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                let y = (i as f64) - center;
+                let x = (j as f64) - center;
+                let xr = x * cos + y * sin;
+                let yr = -x * sin + y * cos;
+
+                *v = (-(xr.powi(2) + gamma.powi(2) * yr.powi(2)) / (2.0 * sigma.powi(2))).exp()
+                    * (2.0 * std::f64::consts::PI * xr / wavelength + phase).cos();
+            }
+        }
+
+        Kernel::new(kernel)
+    }
+    fn scharr_x() -> Self {
+        Self::from_rows(vec![
+            vec![-3.0, 0.0, 3.0],
+            vec![-10.0, 0.0, 10.0],
+            vec![-3.0, 0.0, 3.0],
+        ])
+    }
+    fn scharr_y() -> Self {
+        Self::from_rows(vec![
+            vec![-3.0, -10.0, -3.0],
+            vec![0.0, 0.0, 0.0],
+            vec![3.0, 10.0, 3.0],
+        ])
+    }
+    fn prewitt_x() -> Self {
+        Self::from_rows(vec![
+            vec![-1.0, 0.0, 1.0],
+            vec![-1.0, 0.0, 1.0],
+            vec![-1.0, 0.0, 1.0],
+        ])
+    }
+    fn prewitt_y() -> Self {
+        Self::from_rows(vec![
+            vec![-1.0, -1.0, -1.0],
+            vec![0.0, 0.0, 0.0],
+            vec![1.0, 1.0, 1.0],
+        ])
+    }
+    fn roberts_x() -> Self {
+        Self::from_rows(vec![vec![1.0, 0.0], vec![0.0, -1.0]])
+    }
+    fn roberts_y() -> Self {
+        Self::from_rows(vec![vec![0.0, 1.0], vec![-1.0, 0.0]])
+    }
+    /// Blur along a line through the center at `angle` radians from the
+    /// x axis, normalized to sum to one. The length must be at least 1.
+    fn motion_blur(size: usize, angle: f64) -> Result<Self> {
+        check_size("motion blur length", size)?;
+        let center = (size / 2) as f64;
+        let (sin, cos) = angle.sin_cos();
+
+        let mut kernel: Vec<Vec<f64>> = vec![vec![0.0; size]; size];
+
+        // keep the cells within half a pixel of the line, with y pointing up
+        for (i, row) in kernel.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                let y = center - (i as f64);
+                let x = (j as f64) - center;
+                if (x * sin - y * cos).abs() <= 0.5 {
+                    *v = 1.0;
+                }
+            }
+        }
+
+        let sum: f64 = kernel.iter().flatten().sum();
+        for v in kernel.iter_mut().flatten() {
+            *v /= sum;
+        }
+
+        Kernel::new(kernel)
+    }
+    fn box_blur(size: usize) -> Result<Self> {
+        check_size("box blur size", size)?;
+        let value = 1.0 / (size * size) as f64;
+        Kernel::new(vec![vec![value; size]; size])
+    }
+    /// Flat circular blur with the given radius, normalized to sum to one.
+    fn disk(radius: usize) -> Self {
+        let size = 2 * radius + 1;
+        let r = radius as f64;
+
+        let mut kernel: Vec<Vec<f64>> = vec![vec![0.0; size]; size];
+
+        for (i, row) in kernel.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                let y = (i as f64) - r;
+                let x = (j as f64) - r;
+                if x.powi(2) + y.powi(2) <= r.powi(2) {
+                    *v = 1.0;
+                }
+            }
+        }
+
+        let sum: f64 = kernel.iter().flatten().sum();
+        for v in kernel.iter_mut().flatten() {
+            *v /= sum;
+        }
+
+        Self::from_rows(kernel)
+    }
+    /// Sharpen by adding back `amount` of the detail removed by a gaussian
+    /// blur, `(1 + amount) * identity - amount * gaussian`. Sums to one.
+    fn unsharp_mask(size: usize, sigma: f64, amount: f64) -> Result<Self> {
+        let mut kernel = Self::gaussian(size, sigma)?.into_values();
+        for v in kernel.iter_mut().flatten() {
+            *v *= -amount;
+        }
+        kernel[size / 2][size / 2] += 1.0 + amount;
+
+        Kernel::new(kernel)
+    }
+}
+
+/// reject kernels with no rows, which `Kernel::new` would also refuse
+fn check_size(name: &str, size: usize) -> Result<()> {
+    if size == 0 {
+        return Err(Error::Kernel(format!("{} must be at least 1", name)));
+    }
+    Ok(())
+}
+
+/// reject widths such as sigma that are divided by, and would give NaN
+/// weights when zero
+fn check_positive(name: &str, value: f64) -> Result<()> {
+    if !(value > 0. && value.is_finite()) {
+        return Err(Error::Kernel(format!("{} must be positive", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPS: f64 = 1e-12;
+
+    #[test]
+    fn zero_sum_kernels() {
+        assert!(Kernel::laplacian_of_gaussian(9, 1.4).unwrap().sum().abs() < EPS);
+        assert!(
+            Kernel::difference_of_gaussians(9, 1., 2.)
+                .unwrap()
+                .sum()
+                .abs()
+                < EPS
+        );
+        for k in [
+            Kernel::scharr_x(),
+            Kernel::scharr_y(),
+            Kernel::prewitt_x(),
+            Kernel::prewitt_y(),
+        ] {
+            assert!(k.sum().abs() < EPS);
+        }
+    }
+
+    #[test]
+    fn unit_sum_kernels() {
+        assert!((Kernel::unsharp_mask(5, 1., 1.5).unwrap().sum() - 1.).abs() < EPS);
+        assert!((Kernel::box_blur(4).unwrap().sum() - 1.).abs() < EPS);
+        assert!((Kernel::disk(3).sum() - 1.).abs() < EPS);
+        for angle in [0., 0.3, std::f64::consts::FRAC_PI_4, 2.] {
+            let k = Kernel::motion_blur(9, angle).unwrap();
+            assert!((k.sum() - 1.).abs() < EPS);
+        }
+    }
+
+    #[test]
+    fn motion_blur_rejects_zero_length() {
+        assert!(Kernel::motion_blur(0, 0.).is_err());
+    }
+
+    #[test]
+    fn sized_constructors_reject_zero() {
+        assert!(Kernel::gaussian(0, 1.).is_err());
+        assert!(Kernel::box_blur(0).is_err());
+        assert!(Kernel::laplacian_of_gaussian(0, 1.).is_err());
+        assert!(Kernel::difference_of_gaussians(0, 1., 2.).is_err());
+        assert!(Kernel::gabor(0, 4., 0., 10., 0., 0.5).is_err());
+        assert!(Kernel::unsharp_mask(0, 1., 1.).is_err());
+        assert_eq!(Kernel::disk(0).shape(), (1, 1));
+    }
+
+    #[test]
+    fn gradients_are_antisymmetric() {
+        for (x, y) in [
+            (Kernel::scharr_x(), Kernel::scharr_y()),
+            (Kernel::prewitt_x(), Kernel::prewitt_y()),
+        ] {
+            for i in 0..3 {
+                for j in 0..3 {
+                    assert_eq!(x[i][j], -x[i][2 - j]);
+                    assert_eq!(y[i][j], -y[2 - i][j]);
+                    assert_eq!(x[i][j], y[j][i]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gaussian_is_symmetric() {
+        let g = Kernel::gaussian(7, 1.5).unwrap();
+        for i in 0..7 {
+            for j in 0..7 {
+                assert!((g[i][j] - g[j][i]).abs() < EPS);
+                assert!((g[i][j] - g[6 - i][j]).abs() < EPS);
+                assert!((g[i][j] - g[i][6 - j]).abs() < EPS);
+            }
+        }
+        assert!((g.sum() - 1.).abs() < EPS);
+    }
+
+    #[test]
+    fn widths_must_be_positive() {
+        for bad in [0., -1., f64::NAN, f64::INFINITY] {
+            assert!(Kernel::gaussian(5, bad).is_err());
+            assert!(Kernel::laplacian_of_gaussian(5, bad).is_err());
+            assert!(Kernel::difference_of_gaussians(5, 1., bad).is_err());
+            assert!(Kernel::unsharp_mask(5, bad, 1.).is_err());
+            assert!(Kernel::gabor(5, bad, 0., 4., 0., 0.5).is_err());
+            assert!(Kernel::gabor(5, 2., 0., bad, 0., 0.5).is_err());
+        }
+        assert!(Kernel::unsharp_mask(5, 1., f64::NAN).is_err());
+        assert!(Kernel::new(vec![vec![1., f64::INFINITY]]).is_err());
+    }
+
+    /// a 2x3 kernel anchored at its top right weight
+    fn corner() -> Kernel {
+        Kernel::new(vec![vec![1., 2., 3.], vec![4., 5., 6.]])
+            .unwrap()
+            .with_anchor(0, 2)
+            .unwrap()
+    }
+
+    #[test]
+    fn rotate_carries_the_anchor() {
+        let k = corner();
+        let once = k.rotate(1);
+        assert_eq!(once.values(), &[vec![4., 1.], vec![5., 2.], vec![6., 3.]]);
+        assert_eq!(once.anchor(), (2, 1));
+
+        for turns in 0..8 {
+            let r = k.rotate(turns);
+            let (row, col) = r.anchor();
+            assert_eq!(r[row][col], 3.);
+        }
+        assert_eq!(k.rotate(4), k);
+        assert_eq!(k.flip().anchor(), (1, 0));
+        assert_eq!(k.flip().flip(), k);
+
+        let t = k.transpose();
+        assert_eq!(t.shape(), (3, 2));
+        assert_eq!(t.anchor(), (2, 0));
+        assert_eq!(t[2][0], 3.);
+    }
+
+    #[test]
+    fn try_add_needs_the_same_shape() {
+        let k = corner();
+        let sum = k.try_add(&k.scale(2.)).unwrap();
+        assert_eq!(sum.values(), &[vec![3., 6., 9.], vec![12., 15., 18.]]);
+        assert_eq!(sum.anchor(), k.anchor());
+        assert!(k.try_add(&k.transpose()).is_err());
+    }
+
+    #[test]
+    fn compose_filters_in_one_pass() {
+        use crate::convolve::{convolve_2d, correlate_2d};
+        use crate::padding::Padding;
+
+        let a = corner();
+        let b = Kernel::new(vec![vec![1.], vec![-2.], vec![0.5]]).unwrap();
+        let c = a.compose(&b);
+        assert_eq!(c.shape(), (4, 3));
+        assert_eq!(c.anchor(), (1, 2));
+        assert_eq!(Kernel::identity().compose(&a).values(), {
+            let mut padded = vec![vec![0.; 5]; 4];
+            for (i, row) in a.values().iter().enumerate() {
+                padded[i + 1][1..4].copy_from_slice(row);
+            }
+            padded
+        });
+
+        let img = (0..9)
+            .map(|i| (0..10).map(|j| ((i * 7 + j * 3) % 11) as f64).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let zero = Padding::Zero;
+        let pairs = [
+            (
+                correlate_2d(&correlate_2d(&img, &a, zero), &b, zero),
+                correlate_2d(&img, &c, zero),
+            ),
+            (
+                convolve_2d(&convolve_2d(&img, &a, zero), &b, zero),
+                convolve_2d(&img, &c, zero),
+            ),
+        ];
+        for (twice, once) in pairs {
+            // the edges differ, the first pass drops what falls outside
+            for i in 3..6 {
+                for j in 3..7 {
+                    assert!((twice[i][j] - once[i][j]).abs() < EPS);
+                }
+            }
+        }
     }
 }
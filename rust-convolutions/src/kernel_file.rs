@@ -0,0 +1,335 @@
+//! Reading and writing kernels as text matrices, CSV and JSON
+//!
+//! Text and CSV files hold one kernel row per line, separated by
+//! whitespace or commas. Lines starting with `#` are comments, and the
+//! optional settings are given as `key = value` lines:
+//!
+//! # 3x3 gaussian
+//! divisor = 16
+//! 1 2 1
+//! 2 4 2
+//! 1 2 1
+//!
+//! JSON files are either a bare matrix, or an object with `values` and the
+//! optional `divisor`, `scale` and `anchor` fields:
+//!
+//! {"values": [[1, 2, 1], [2, 4, 2], [1, 2, 1]], "divisor": 16, "anchor": [1, 1]}
+//!
+//! Every number must be finite. `kernel save <spec> <file>` writes any
+//! kernel spec in these formats.
+
+use crate::kernel::Kernel;
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl KernelFormat {
+    /// Pick the format from a file extension, `.csv`, `.json`, and
+    /// anything else as text.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => KernelFormat::Csv,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => KernelFormat::Json,
+            _ => KernelFormat::Text,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KernelFile {
+    values: Vec<Vec<f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    divisor: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scale: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    anchor: Option<(usize, usize)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonKernel {
+    Matrix(Vec<Vec<f64>>),
+    File(KernelFile),
+}
+
+/// read a kernel, with the format chosen by the file extension
+pub fn load_kernel(path: impl AsRef<Path>) -> Result<Kernel> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    parse_kernel(&contents, KernelFormat::from_path(path))
+}
+
+/// write a kernel, with the format chosen by the file extension
+pub fn save_kernel(kernel: &Kernel, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let contents = format_kernel(kernel, KernelFormat::from_path(path))?;
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+pub fn parse_kernel(contents: &str, format: KernelFormat) -> Result<Kernel> {
+    let file = match format {
+        KernelFormat::Text => parse_matrix(contents, false)?,
+        KernelFormat::Csv => parse_matrix(contents, true)?,
+        KernelFormat::Json => match serde_json::from_str(contents)? {
+            JsonKernel::Matrix(values) => KernelFile {
+                values,
+                divisor: None,
+                scale: None,
+                anchor: None,
+            },
+            JsonKernel::File(file) => file,
+        },
+    };
+
+    build(file)
+}
+
+pub fn format_kernel(kernel: &Kernel, format: KernelFormat) -> Result<String> {
+    // only write the anchor when it is not the default center
+    let center = (kernel.rows() / 2, kernel.cols() / 2);
+    let anchor = if kernel.anchor() != center {
+        Some(kernel.anchor())
+    } else {
+        None
+    };
+
+    let separator = match format {
+        KernelFormat::Text => " ",
+        KernelFormat::Csv => ",",
+        KernelFormat::Json => {
+            let file = KernelFile {
+                values: kernel.values().to_owned(),
+                divisor: None,
+                scale: None,
+                anchor,
+            };
+            return Ok(serde_json::to_string_pretty(&file)?);
+        }
+    };
+
+    let mut out = String::new();
+    if let Some((row, col)) = anchor {
+        out.push_str(&format!("anchor = {}{}{}\n", row, separator, col));
+    }
+    for row in kernel.values().iter() {
+        let line = row
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+            .join(separator);
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// parse the rows and `key = value` settings of a text or csv file
+fn parse_matrix(contents: &str, csv: bool) -> Result<KernelFile> {
+    let mut file = KernelFile {
+        values: vec![],
+        divisor: None,
+        scale: None,
+        anchor: None,
+    };
+
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
+            match key.trim() {
+                "divisor" => file.divisor = Some(parse_number(value, n)?),
+                "scale" => file.scale = Some(parse_number(value, n)?),
+                "anchor" => {
+                    let parts = split_values(value, csv);
+                    if parts.len() != 2 {
+                        return Err(Error::Kernel(format!(
+                            "line {}: anchor needs a row and a column",
+                            n + 1
+                        )));
+                    }
+                    file.anchor = Some((parse_index(parts[0], n)?, parse_index(parts[1], n)?));
+                }
+                key => {
+                    return Err(Error::Kernel(format!(
+                        "line {}: unknown setting '{}'",
+                        n + 1,
+                        key
+                    )))
+                }
+            }
+            continue;
+        }
+
+        let row = split_values(line, csv)
+            .into_iter()
+            .map(|v| parse_number(v, n))
+            .collect::<Result<Vec<f64>>>()?;
+        file.values.push(row);
+    }
+
+    Ok(file)
+}
+
+fn split_values(line: &str, csv: bool) -> Vec<&str> {
+    if csv {
+        return line.split(',').map(|v| v.trim()).collect();
+    }
+    line.split_whitespace().collect()
+}
+
+/// parse a finite number, rust would also accept `nan` and `inf`
+fn parse_number(value: &str, line: usize) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(v),
+        _ => Err(Error::Kernel(format!(
+            "line {}: '{}' is not a finite number",
+            line + 1,
+            value
+        ))),
+    }
+}
+
+fn parse_index(value: &str, line: usize) -> Result<usize> {
+    value
+        .parse::<usize>()
+        .map_err(|_| Error::Kernel(format!("line {}: '{}' is not an index", line + 1, value)))
+}
+
+/// validate the values and apply the optional settings
+fn build(file: KernelFile) -> Result<Kernel> {
+    let mut kernel = Kernel::new(file.values)?;
+
+    if let Some(divisor) = file.divisor {
+        if divisor == 0.0 {
+            return Err(Error::Kernel("divisor must not be zero".to_string()));
+        }
+        kernel = kernel.scale(1.0 / divisor);
+    }
+    if let Some(scale) = file.scale {
+        kernel = kernel.scale(scale);
+    }
+    if let Some((row, col)) = file.anchor {
+        kernel = kernel.with_anchor(row, col)?;
+    }
+    // a tiny divisor or huge scale can still overflow
+    if kernel.values().iter().flatten().any(|v| !v.is_finite()) {
+        return Err(Error::Kernel(
+            "divisor and scale make the weights overflow".to_string(),
+        ));
+    }
+
+    Ok(kernel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_text_with_settings() {
+        let text = "# 3x3 gaussian\ndivisor = 16\n\n1 2 1\n2  4\t2\n1 2 1\n";
+        let k = parse_kernel(text, KernelFormat::Text).unwrap();
+        assert_eq!(k.shape(), (3, 3));
+        assert_eq!(k.anchor(), (1, 1));
+        assert_eq!(k[1][1], 0.25);
+        assert_eq!(k.sum(), 1.);
+
+        let text = "scale = -2\nanchor = 0 2\n1 0.5 0\n";
+        let k = parse_kernel(text, KernelFormat::Text).unwrap();
+        assert_eq!(k.values(), &[vec![-2., -1., 0.]]);
+        assert_eq!(k.anchor(), (0, 2));
+    }
+
+    #[test]
+    fn parses_csv() {
+        let csv = "divisor=2\nanchor = 1, 0\n1, 2\n 3 ,4\n";
+        let k = parse_kernel(csv, KernelFormat::Csv).unwrap();
+        assert_eq!(k.values(), &[vec![0.5, 1.], vec![1.5, 2.]]);
+        assert_eq!(k.anchor(), (1, 0));
+    }
+
+    #[test]
+    fn parses_json_matrices_and_objects() {
+        let k = parse_kernel("[[1, 2], [3, 4]]", KernelFormat::Json).unwrap();
+        assert_eq!(k.values(), &[vec![1., 2.], vec![3., 4.]]);
+        assert_eq!(k.anchor(), (1, 1));
+
+        let json = r#"{"values": [[1, 2, 1], [2, 4, 2], [1, 2, 1]], "divisor": 16, "scale": 2, "anchor": [0, 1]}"#;
+        let k = parse_kernel(json, KernelFormat::Json).unwrap();
+        assert_eq!(k[1][1], 0.5);
+        assert_eq!(k.anchor(), (0, 1));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let cases = [
+            ("", KernelFormat::Text),
+            ("1 2\n3\n", KernelFormat::Text),
+            ("1 nan\n", KernelFormat::Text),
+            ("1 inf\n", KernelFormat::Text),
+            ("1,-inf\n", KernelFormat::Csv),
+            ("1,,2\n", KernelFormat::Csv),
+            ("divisor = 0\n1\n", KernelFormat::Text),
+            ("divisor = NaN\n1\n", KernelFormat::Text),
+            ("divisor = 1e-320\n1e10\n", KernelFormat::Text),
+            ("anchor = 2 0\n1 2\n", KernelFormat::Text),
+            ("anchor = 0\n1 2\n", KernelFormat::Text),
+            ("size = 3\n1\n", KernelFormat::Text),
+            ("[[1, 2], [3]]", KernelFormat::Json),
+            (r#"{"values": [[1]], "divisor": 0}"#, KernelFormat::Json),
+        ];
+        for (contents, format) in cases {
+            assert!(
+                parse_kernel(contents, format).is_err(),
+                "{:?} {:?}",
+                contents,
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn format_then_parse_gives_the_kernel_back() {
+        let kernels = [
+            Kernel::new(vec![vec![0.1, -2.5, 1. / 3.], vec![4., 0., 1e-7]]).unwrap(),
+            Kernel::new(vec![vec![1., 2.], vec![3., 4.], vec![5., 6.]])
+                .unwrap()
+                .with_anchor(2, 0)
+                .unwrap(),
+        ];
+        for k in kernels {
+            for format in [KernelFormat::Text, KernelFormat::Csv, KernelFormat::Json] {
+                let contents = format_kernel(&k, format).unwrap();
+                assert_eq!(parse_kernel(&contents, format).unwrap(), k, "{}", contents);
+            }
+        }
+    }
+
+    #[test]
+    fn save_then_load_picks_the_format_from_the_extension() {
+        let k = Kernel::new(vec![vec![1., 2., 3.]])
+            .unwrap()
+            .with_anchor(0, 0)
+            .unwrap();
+        for ext in ["txt", "csv", "json"] {
+            let path = std::env::temp_dir().join(format!("convolutions-kernel.{}", ext));
+            save_kernel(&k, &path).unwrap();
+            assert_eq!(load_kernel(&path).unwrap(), k);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
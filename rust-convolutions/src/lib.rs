@@ -0,0 +1,33 @@
+//! Convolutions, pooling and fourier transforms on images and signals
+//!
+//! The `convolutions` binary is a thin wrapper over `cli`. Everything it
+//! runs is public here, so other crates can add their own kernels,
+//! reducers or pipeline steps without forking.
+
+pub mod batch;
+pub mod cli;
+pub mod color;
+pub mod conv1d;
+pub mod convolve;
+pub mod deconvolve;
+pub mod dft;
+pub mod error;
+pub mod fir;
+pub mod frequency;
+pub mod image_io;
+pub mod kernel;
+pub mod kernel_file;
+pub mod mapping;
+pub mod morphology;
+pub mod padding;
+pub mod pipeline;
+pub mod pooling;
+pub mod prelude;
+pub mod rank;
+pub mod registry;
+pub mod signal;
+pub mod spectrum;
+pub mod stft;
+pub mod streaming;
+pub mod volume;
+pub mod windowing;
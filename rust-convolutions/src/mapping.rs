@@ -0,0 +1,201 @@
+//! Mapping filter results into the 0-1 range for output
+//!
+//! Filters such as `edge_x` produce negative values, and sharpening or fft
+//! results can go above 1, so the mapping used when writing an image is
+//! chosen explicitly instead of being hidden in the filters.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueMap {
+    /// clip to 0-1
+    Clamp,
+    /// rescale linearly so the smallest value is 0 and the largest 1
+    MinMax,
+    /// absolute value, for the magnitude of signed filters
+    Abs,
+    /// add 0.5 (128 of 255), so zero of a signed filter is mid grey
+    Offset,
+    /// rescale so the `low` and `high` percentiles (0-100) map to 0 and 1,
+    /// ignoring outliers
+    Percentile { low: f64, high: f64 },
+    /// clip to 0-1 and raise to `1 / gamma`
+    Gamma(f64),
+}
+
+/// Parses `clamp`, `minmax`, `abs`, `offset`, `percentile:<low>:<high>`
+/// and `gamma:<gamma>`.
+impl FromStr for ValueMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<&str>>();
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("'{}' is not a number", v))
+        };
+
+        match parts.as_slice() {
+            ["clamp"] => Ok(ValueMap::Clamp),
+            ["minmax"] => Ok(ValueMap::MinMax),
+            ["abs"] => Ok(ValueMap::Abs),
+            ["offset"] => Ok(ValueMap::Offset),
+            ["percentile"] => Ok(ValueMap::Percentile {
+                low: 1.,
+                high: 99.,
+            }),
+            ["percentile", low, high] => {
+                let (low, high) = (number(low)?, number(high)?);
+                if !(0. ..=100.).contains(&low) || !(0. ..=100.).contains(&high) || low >= high {
+                    return Err("percentiles must be 0-100 with low below high".to_string());
+                }
+                Ok(ValueMap::Percentile { low, high })
+            }
+            ["gamma", gamma] => {
+                let gamma = number(gamma)?;
+                if !(gamma > 0. && gamma.is_finite()) {
+                    return Err("gamma must be positive".to_string());
+                }
+                Ok(ValueMap::Gamma(gamma))
+            }
+            _ => Err(format!(
+                "unknown mapping '{}', expected clamp, minmax, abs, offset, percentile:<low>:<high> or gamma:<gamma>",
+                s
+            )),
+        }
+    }
+}
+
+/// apply each mapping in order
+pub fn apply_all(img: &[Vec<f64>], maps: &[ValueMap]) -> Vec<Vec<f64>> {
+    let mut out = img.to_owned();
+    for map in maps {
+        out = apply(&out, *map);
+    }
+    out
+}
+
+pub fn apply(img: &[Vec<f64>], map: ValueMap) -> Vec<Vec<f64>> {
+    match map {
+        ValueMap::Clamp => map_values(img, |v| v.clamp(0., 1.)),
+        ValueMap::MinMax => {
+            let min = img.iter().flatten().cloned().fold(f64::INFINITY, f64::min);
+            let max = img
+                .iter()
+                .flatten()
+                .cloned()
+                .fold(f64::NEG_INFINITY, f64::max);
+            stretch(img, min, max)
+        }
+        ValueMap::Abs => map_values(img, |v| v.abs()),
+        ValueMap::Offset => map_values(img, |v| v + 0.5),
+        ValueMap::Percentile { low, high } => {
+            let mut values = img.iter().flatten().cloned().collect::<Vec<f64>>();
+            if values.is_empty() {
+                return img.to_owned();
+            }
+            values.sort_by(f64::total_cmp);
+            let at = |p: f64| values[((p / 100.) * (values.len() - 1) as f64).round() as usize];
+            let (min, max) = (at(low), at(high));
+            map_values(&stretch(img, min, max), |v| v.clamp(0., 1.))
+        }
+        ValueMap::Gamma(gamma) => map_values(img, |v| v.clamp(0., 1.).powf(1. / gamma)),
+    }
+}
+
+/// map `min` to 0 and `max` to 1, a flat image becomes all zeros
+fn stretch(img: &[Vec<f64>], min: f64, max: f64) -> Vec<Vec<f64>> {
+    let range = max - min;
+    map_values(img, |v| if range > 0. { (v - min) / range } else { 0. })
+}
+
+fn map_values<F>(img: &[Vec<f64>], f: F) -> Vec<Vec<f64>>
+where
+    F: Fn(f64) -> f64,
+{
+    img.iter()
+        .map(|row| row.iter().map(|v| f(*v)).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> ValueMap {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn minmax_stretches_to_0_1() {
+        let img = vec![vec![-2., 0.], vec![2., 6.]];
+        assert_eq!(
+            apply(&img, ValueMap::MinMax),
+            vec![vec![0., 0.25], vec![0.5, 1.]]
+        );
+        // a flat image has no range to stretch
+        assert_eq!(apply(&[vec![3.; 4]], ValueMap::MinMax), vec![vec![0.; 4]]);
+    }
+
+    #[test]
+    fn percentile_ignores_outliers() {
+        // 0-99 with one huge outlier
+        let mut values = (0..100).map(|v| v as f64).collect::<Vec<f64>>();
+        values[50] = 1e6;
+        let img = vec![values];
+        let out = apply(&img, parse("percentile:10:90"));
+
+        let low = out[0].iter().filter(|v| **v == 0.).count();
+        let high = out[0].iter().filter(|v| **v == 1.).count();
+        assert_eq!((low, high), (11, 11));
+        assert!(out[0].iter().all(|v| (0. ..=1.).contains(v)));
+        assert!((out[0][30] - 20. / 80.).abs() < 1e-12);
+
+        let empty: Vec<Vec<f64>> = vec![];
+        assert!(apply(&empty, parse("percentile")).is_empty());
+        let blank: Vec<Vec<f64>> = vec![vec![]];
+        assert_eq!(apply(&blank, parse("percentile")), blank);
+    }
+
+    #[test]
+    fn gamma_clips_then_brightens() {
+        let img = vec![vec![-1., 0.25, 1., 4.]];
+        assert_eq!(apply(&img, parse("gamma:2")), vec![vec![0., 0.5, 1., 1.]]);
+        assert_eq!(apply(&img, parse("gamma:1")), apply(&img, ValueMap::Clamp));
+    }
+
+    #[test]
+    fn offset_and_abs_centre_signed_results() {
+        let img = vec![vec![-0.5, 0., 0.25]];
+        assert_eq!(apply(&img, ValueMap::Offset), vec![vec![0., 0.5, 0.75]]);
+        assert_eq!(
+            apply_all(&img, &[ValueMap::Abs, ValueMap::Offset]),
+            vec![vec![1., 0.5, 0.75]]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_mappings() {
+        for s in [
+            "stretch",
+            "percentile:5",
+            "percentile:90:10",
+            "percentile:50:50",
+            "percentile:-1:50",
+            "percentile:1:101",
+            "percentile:nan:50",
+            "gamma",
+            "gamma:0",
+            "gamma:-2",
+            "gamma:nan",
+            "gamma:inf",
+            "gamma:x",
+        ] {
+            assert!(s.parse::<ValueMap>().is_err(), "{}", s);
+        }
+        assert_eq!(
+            parse("percentile"),
+            ValueMap::Percentile { low: 1., high: 99. }
+        );
+    }
+}
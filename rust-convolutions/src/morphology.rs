@@ -0,0 +1,370 @@
+//! Morphological operations
+
+use crate::padding::Padding;
+use crate::pooling::{self, Window};
+use crate::prelude::*;
+use std::str::FromStr;
+
+/// largest disk radius, the same bound as kernel sizes in specs
+const MAX_RADIUS: usize = 500;
+
+/// The shape used to probe the image. Masks are square and odd sized,
+/// with the origin at the center.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// full `size` x `size` square
+    Square(usize),
+    /// disk with the given radius, `2 * radius + 1` wide
+    Disk(usize),
+    /// center row and column of a `size` x `size` square
+    Cross(usize),
+    /// any square mask, `true` marks the pixels in the element
+    Mask(Vec<Vec<bool>>),
+}
+
+/// Parses `square:<size>`, `disk:<radius>` and `cross:<size>`.
+impl FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, n) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <shape>:<size>, not '{}'", s))?;
+        let n = n
+            .parse::<usize>()
+            .map_err(|_| format!("'{}' is not a whole number", n))?;
+        match name {
+            "square" => Ok(Shape::Square(n)),
+            "disk" => Ok(Shape::Disk(n)),
+            "cross" => Ok(Shape::Cross(n)),
+            _ => Err(format!(
+                "unknown element '{}', expected square, disk or cross",
+                name
+            )),
+        }
+    }
+}
+
+/// A checked `Shape` with its mask, made by `StructuringElement::new`.
+#[derive(Debug, Clone)]
+pub struct StructuringElement {
+    shape: Shape,
+    mask: Vec<Vec<bool>>,
+}
+
+impl StructuringElement {
+    /// Check the shape and build its mask. Squares and crosses need an odd
+    /// size, masks must be square, odd sized and have a pixel set.
+    pub fn new(shape: Shape) -> Result<Self> {
+        let mask = match &shape {
+            Shape::Square(size) => {
+                check_size(*size)?;
+                vec![vec![true; *size]; *size]
+            }
+            Shape::Disk(radius) => {
+                if *radius > MAX_RADIUS {
+                    return Err(Error::Kernel(format!(
+                        "disk radius must be at most {}",
+                        MAX_RADIUS
+                    )));
+                }
+                let r = *radius as i64;
+                (-r..=r)
+                    .map(|y| (-r..=r).map(|x| x * x + y * y <= r * r).collect())
+                    .collect()
+            }
+            Shape::Cross(size) => {
+                check_size(*size)?;
+                let center = size / 2;
+                (0..*size)
+                    .map(|i| (0..*size).map(|j| i == center || j == center).collect())
+                    .collect()
+            }
+            Shape::Mask(mask) => {
+                check_size(mask.len())?;
+                if mask.iter().any(|row| row.len() != mask.len()) {
+                    return Err(Error::Kernel(
+                        "structuring element mask must be square".to_string(),
+                    ));
+                }
+                if !mask.iter().flatten().any(|set| *set) {
+                    return Err(Error::Kernel(
+                        "structuring element mask has no pixels set".to_string(),
+                    ));
+                }
+                mask.to_owned()
+            }
+        };
+        Ok(StructuringElement { shape, mask })
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// side length of the element mask
+    pub fn size(&self) -> usize {
+        self.mask.len()
+    }
+
+    /// the element as a square boolean mask
+    pub fn mask(&self) -> &[Vec<bool>] {
+        &self.mask
+    }
+}
+
+/// A morphological operation, for pipelines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Dilate,
+    Erode,
+    Open,
+    Close,
+    Gradient,
+    TopHat,
+    BlackTopHat,
+}
+
+/// Parses `dilate`, `erode`, `open`, `close`, `gradient`, `top_hat` and
+/// `black_top_hat`.
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dilate" => Ok(Operation::Dilate),
+            "erode" => Ok(Operation::Erode),
+            "open" => Ok(Operation::Open),
+            "close" => Ok(Operation::Close),
+            "gradient" => Ok(Operation::Gradient),
+            "top_hat" => Ok(Operation::TopHat),
+            "black_top_hat" => Ok(Operation::BlackTopHat),
+            _ => Err(format!(
+                "unknown morphology '{}', expected dilate, erode, open, close, gradient, top_hat or black_top_hat",
+                s
+            )),
+        }
+    }
+}
+
+impl Operation {
+    pub fn apply(&self, input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+        match self {
+            Operation::Dilate => dilate(input, element),
+            Operation::Erode => erode(input, element),
+            Operation::Open => open(input, element),
+            Operation::Close => close(input, element),
+            Operation::Gradient => gradient(input, element),
+            Operation::TopHat => top_hat(input, element),
+            Operation::BlackTopHat => black_top_hat(input, element),
+        }
+    }
+}
+
+/// Grow bright regions: every pixel becomes the maximum of the input
+/// under the reflected element centered on it.
+pub fn dilate(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    // pad with the identity of max so the border does not leak in
+    let mode = Padding::Constant(f64::NEG_INFINITY);
+    if let Shape::Square(size) = element.shape() {
+        return pooling::max_pool_padded(input, *size, mode);
+    }
+
+    // dilation uses the element rotated by 180 degrees
+    let size = element.size();
+    let offsets = offsets(element.mask())
+        .into_iter()
+        .map(|(i, j)| (size - 1 - i, size - 1 - j))
+        .collect::<Vec<(usize, usize)>>();
+
+    pooling::pool_padded(input, size, mode, &mut |w: &Window| {
+        offsets
+            .iter()
+            .fold(f64::NEG_INFINITY, |acc, &(i, j)| acc.max(w.get(i, j)))
+    })
+}
+
+/// Shrink bright regions: every pixel becomes the minimum of the input
+/// under the element centered on it.
+pub fn erode(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    // pad with the identity of min so the border does not leak in
+    let mode = Padding::Constant(f64::INFINITY);
+    if let Shape::Square(size) = element.shape() {
+        return pooling::min_pool_padded(input, *size, mode);
+    }
+
+    let offsets = offsets(element.mask());
+
+    pooling::pool_padded(input, element.size(), mode, &mut |w: &Window| {
+        offsets
+            .iter()
+            .fold(f64::INFINITY, |acc, &(i, j)| acc.min(w.get(i, j)))
+    })
+}
+
+/// erosion followed by dilation, removes bright details smaller than
+/// the element
+pub fn open(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    dilate(&erode(input, element), element)
+}
+
+/// dilation followed by erosion, fills dark details smaller than the
+/// element
+pub fn close(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    erode(&dilate(input, element), element)
+}
+
+/// difference between the dilation and the erosion, outlines edges
+pub fn gradient(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    subtract(&dilate(input, element), &erode(input, element))
+}
+
+/// white top-hat, the bright details removed by an opening
+pub fn top_hat(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    subtract(input, &open(input, element))
+}
+
+/// black top-hat, the dark details filled by a closing
+pub fn black_top_hat(input: &[Vec<f64>], element: &StructuringElement) -> Vec<Vec<f64>> {
+    subtract(&close(input, element), input)
+}
+
+/// positions of the set pixels in a mask
+fn offsets(mask: &[Vec<bool>]) -> Vec<(usize, usize)> {
+    let mut out = vec![];
+    for (i, row) in mask.iter().enumerate() {
+        for (j, set) in row.iter().enumerate() {
+            if *set {
+                out.push((i, j));
+            }
+        }
+    }
+    out
+}
+
+/// sizes must be odd so the element has a center pixel
+fn check_size(size: usize) -> Result<()> {
+    if size.is_multiple_of(2) {
+        return Err(Error::Kernel(format!(
+            "structuring elements need an odd size, not {}",
+            size
+        )));
+    }
+    Ok(())
+}
+
+fn subtract(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    a.iter()
+        .zip(b.iter())
+        .map(|(ra, rb)| ra.iter().zip(rb.iter()).map(|(x, y)| x - y).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(shape: Shape) -> StructuringElement {
+        StructuringElement::new(shape).unwrap()
+    }
+
+    /// a bright 2x2 block, a lone bright pixel and a dark hole
+    fn image() -> Vec<Vec<f64>> {
+        vec![
+            vec![0., 0., 0., 0., 0., 0., 0.],
+            vec![0., 5., 5., 0., 0., 0., 0.],
+            vec![0., 5., 5., 0., 0., 9., 0.],
+            vec![0., 0., 0., 0., 0., 0., 0.],
+            vec![3., 3., 3., 0., 0., 0., 0.],
+            vec![3., 1., 3., 0., 0., 0., 0.],
+            vec![3., 3., 3., 0., 0., 0., 0.],
+        ]
+    }
+
+    #[test]
+    fn every_shape_is_checked() {
+        for shape in [
+            Shape::Square(0),
+            Shape::Square(4),
+            Shape::Cross(0),
+            Shape::Cross(2),
+            Shape::Disk(MAX_RADIUS + 1),
+            Shape::Mask(vec![]),
+            Shape::Mask(vec![vec![true; 2]; 2]),
+            Shape::Mask(vec![vec![true; 3]; 1]),
+            Shape::Mask(vec![vec![true; 3], vec![true; 2], vec![true; 3]]),
+            Shape::Mask(vec![vec![false; 3]; 3]),
+        ] {
+            assert!(
+                StructuringElement::new(shape.clone()).is_err(),
+                "{:?}",
+                shape
+            );
+        }
+        assert_eq!(element(Shape::Disk(0)).mask(), &[vec![true]]);
+        assert_eq!(
+            element(Shape::Cross(3)).mask(),
+            &[
+                vec![false, true, false],
+                vec![true, true, true],
+                vec![false, true, false]
+            ]
+        );
+    }
+
+    #[test]
+    fn dilate_and_erode_a_known_image() {
+        let img = image();
+        let square = element(Shape::Square(3));
+        let cross = element(Shape::Cross(3));
+
+        let dilated = dilate(&img, &square);
+        assert_eq!(dilated[0][..4], [5., 5., 5., 5.]);
+        assert_eq!(dilated[1][4..], [9., 9., 9.]);
+        assert_eq!(dilated[5][..4], [3., 3., 3., 3.]);
+
+        // the cross leaves out the diagonal neighbours
+        let dilated = dilate(&img, &cross);
+        assert_eq!(dilated[1][4..], [0., 9., 0.]);
+        assert_eq!(dilated[0][..4], [0., 5., 5., 0.]);
+
+        // erosion pads with +inf, so the border does not darken the ring
+        // and only the hole does
+        let eroded = erode(&img, &square);
+        assert_eq!(eroded[1][1], 0.);
+        assert_eq!(eroded[5][..3], [1., 1., 0.]);
+        assert_eq!(eroded[6][..2], [1., 1.]);
+
+        // a mask equal to the square matches the fast square path
+        let mask = element(Shape::Mask(vec![vec![true; 3]; 3]));
+        assert_eq!(dilate(&img, &mask), dilate(&img, &square));
+        assert_eq!(erode(&img, &mask), erode(&img, &square));
+    }
+
+    #[test]
+    fn open_close_and_top_hats() {
+        let img = image();
+        let square = element(Shape::Square(3));
+
+        // opening removes the 2x2 block and the lone pixel, which are
+        // smaller than the element, and keeps the 3x3 ring
+        let opened = open(&img, &square);
+        assert!(opened[..4].iter().flatten().all(|v| *v == 0.));
+        assert_eq!(opened[5][..3], [1., 1., 1.]);
+
+        // closing fills the hole in the ring
+        let closed = close(&img, &square);
+        assert_eq!(closed[5][1], 3.);
+        assert_eq!(closed[2][5], 9.);
+
+        // the top-hats are exactly what was removed and filled
+        let top = top_hat(&img, &square);
+        assert_eq!(top[2][5], 9.);
+        assert_eq!(top[1][1], 5.);
+        assert_eq!(black_top_hat(&img, &square)[5][1], 2.);
+
+        let edges = gradient(&img, &square);
+        assert_eq!(edges[2][5], 9.);
+        assert_eq!(edges[5][5], 0.);
+    }
+}
@@ -1,9 +1,104 @@
+use std::str::FromStr;
+
+/// The padding strategies available to filters that take a padding mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Padding {
+    Zero,
+    Reflection,
+    Constant(f64),
+}
+
+/// Parses `zero`, `reflection` (or `reflect`) and `constant:<value>`.
+impl FromStr for Padding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Padding::Zero),
+            "reflection" | "reflect" => Ok(Padding::Reflection),
+            _ => match s.strip_prefix("constant:") {
+                Some(value) => value
+                    .parse::<f64>()
+                    .map(Padding::Constant)
+                    .map_err(|_| format!("'{}' is not a number", value)),
+                None => Err(format!(
+                    "unknown padding '{}', expected zero, reflection or constant:<value>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Pads the matrix with the chosen `mode`, based on the kernel size.
+pub fn pad(input: &[Vec<f64>], size: usize, mode: Padding) -> Vec<Vec<f64>> {
+    match mode {
+        Padding::Zero => zero_pad(input, size),
+        Padding::Reflection => reflection_pad(input, size),
+        Padding::Constant(value) => constant_pad(input, size, value),
+    }
+}
+
+/// Pads the matrix by an explicit amount on each side using `mode`, so
+/// kernels with an off-center anchor can be lined up with the input.
+/// Reflection mirrors about the edge pixel without repeating it, the same
+/// way as `reflection_pad`, and keeps mirroring for pads wider than the
+/// input.
+pub fn pad_sides(
+    input: &[Vec<f64>],
+    top: usize,
+    bottom: usize,
+    left: usize,
+    right: usize,
+    mode: Padding,
+) -> Vec<Vec<f64>> {
+    let (rows, cols) = (input.len(), input[0].len());
+    let mut out = vec![vec![0.0; cols + left + right]; rows + top + bottom];
+
+    for i in 0..out.len() {
+        for j in 0..out[0].len() {
+            let y = i as i64 - top as i64;
+            let x = j as i64 - left as i64;
+            let inside = y >= 0 && y < rows as i64 && x >= 0 && x < cols as i64;
+
+            out[i][j] = match mode {
+                _ if inside => input[y as usize][x as usize],
+                Padding::Zero => 0.0,
+                Padding::Constant(value) => value,
+                Padding::Reflection => input[reflect(y, rows)][reflect(x, cols)],
+            };
+        }
+    }
+
+    out
+}
+
+/// mirror an out of range index back into `0..len`
+pub fn reflect(index: i64, len: usize) -> usize {
+    if len == 1 {
+        return 0;
+    }
+    let period = 2 * (len as i64 - 1);
+    let i = index.rem_euclid(period);
+    if i < len as i64 {
+        return i as usize;
+    }
+    (period - i) as usize
+}
+
 /// Pads the matrix using zero padding, based on the kernel size.
 /// zero padding is where the vector is center-padded as such where
 /// when a convolution occurs the output will be the same size as the
 /// input.
-pub fn zero_pad(input: &Vec<Vec<f64>>, size: usize) -> Vec<Vec<f64>> {
-    let mut out: Vec<Vec<f64>> = vec![vec![0.0; input[0].len() + size - 1]; input.len() + size - 1];
+pub fn zero_pad(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    constant_pad(input, size, 0.0)
+}
+
+/// Pads the matrix with a constant `value`, placing the input exactly
+/// where `zero_pad` would.
+pub fn constant_pad(input: &[Vec<f64>], size: usize, value: f64) -> Vec<Vec<f64>> {
+    let mut out: Vec<Vec<f64>> =
+        vec![vec![value; input[0].len() + size - 1]; input.len() + size - 1];
 
     for i in 0..input.len() {
         for j in 0..input[0].len() {
@@ -11,7 +106,7 @@ pub fn zero_pad(input: &Vec<Vec<f64>>, size: usize) -> Vec<Vec<f64>> {
         }
     }
 
-    return out;
+    out
 }
 
 /// Pads a matrix using reflection, based on the kernel size.
@@ -30,51 +125,48 @@ pub fn zero_pad(input: &Vec<Vec<f64>>, size: usize) -> Vec<Vec<f64>> {
 ///           [5,4,5,6,5]
 ///           [8,7,8,9,8]
 ///           [5,4,5,6,5]
-pub fn reflection_pad(input: &Vec<Vec<f64>>, size: usize) -> Vec<Vec<f64>> {
-    let (input_rows, input_cols) = (input.len(), input[0].len());
-
-    // calculate the amount of padding needed for each side
-    let (pad_top, pad_bottom) = ((size - 1) / 2, size / 2);
-    let (pad_left, pad_right) = ((size - 1) / 2, size / 2);
+///
+/// The input is placed exactly where `zero_pad` would, so for even sizes
+/// there is one more row and column of padding before it than after. Pads
+/// wider than the input keep mirroring, see `pad_sides`.
+pub fn reflection_pad(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    let (before, after) = (size / 2, (size - 1) - size / 2);
+    pad_sides(input, before, after, before, after, Padding::Reflection)
+}
 
-    // create the output matrix with the correct size
-    let mut output =
-        vec![vec![0.0; input_cols + pad_left + pad_right]; input_rows + pad_top + pad_bottom];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // fill in the original input values
-    for i in 0..input_rows {
-        for j in 0..input_cols {
-            output[i + pad_top][j + pad_left] = input[i][j];
-        }
+    fn image() -> Vec<Vec<f64>> {
+        (0..4)
+            .map(|i| (0..5).map(|j| (i * 5 + j) as f64).collect())
+            .collect()
     }
 
-    // reflect the top and bottom rows
-    for i in 0..pad_top {
-        let top_row = input[pad_top - i].clone();
-        let bottom_row = input[input_rows - 1 - (pad_bottom - i)].clone();
-        for j in 0..input_cols {
-            output[i][j + pad_left] = top_row[j];
-            let l = output.len();
-            output[l - 1 - i][j + pad_left] = bottom_row[j];
-        }
-    }
+    #[test]
+    fn reflection_mirrors_without_repeating_the_edge() {
+        let img = image();
+        for size in 1..8 {
+            let padded = pad(&img, size, Padding::Reflection);
+            assert_eq!(padded.len(), img.len() + size - 1);
+            assert_eq!(padded[0].len(), img[0].len() + size - 1);
 
-    // reflect the left and right columns
-    for j in 0..pad_left {
-        let left_col = output
-            .iter()
-            .map(|row| row[pad_left - j + 1])
-            .collect::<Vec<f64>>();
-        let right_col = output
-            .iter()
-            .map(|row| row[input_cols - 1 + pad_left - (pad_right - j)])
-            .collect::<Vec<f64>>();
-        for i in 0..output.len() {
-            output[i][j] = left_col[i];
-            let l = output[0].len();
-            output[i][l - 1 - j] = right_col[i];
+            let before = (size / 2) as i64;
+            for (i, row) in padded.iter().enumerate() {
+                for (j, v) in row.iter().enumerate() {
+                    let y = reflect(i as i64 - before, img.len());
+                    let x = reflect(j as i64 - before, img[0].len());
+                    assert_eq!(*v, img[y][x], "size {} at ({}, {})", size, i, j);
+                }
+            }
         }
     }
 
-    output
+    #[test]
+    fn reflection_of_size_5() {
+        let padded = reflection_pad(&image(), 5);
+        assert_eq!(padded[2], vec![2., 1., 0., 1., 2., 3., 4., 3., 2.]);
+        assert_eq!(padded[0][2..7], [10., 11., 12., 13., 14.]);
+    }
 }
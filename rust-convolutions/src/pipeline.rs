@@ -0,0 +1,442 @@
+//! Declarative filter pipelines
+//!
+//! A pipeline is a list of steps applied in order, read from TOML or JSON.
+//! Each step names its operation with `op`:
+//!
+//! name = "edges"
+//!
+//! [[steps]]
+//! op = "convolve"
+//! kernel = "gaussian:5:1"
+//!
+//! [[steps]]
+//! op = "gradient"
+//! x = "edge_x"
+//! y = "edge_y"
+//!
+//! [[steps]]
+//! op = "normalize"
+//!
+//! [[steps]]
+//! op = "threshold"
+//! value = 0.3
+//!
+//! Kernel specs, padding modes and pooling methods are checked by
+//! `compile` before any image is touched.
+
+use crate::convolve;
+use crate::kernel::Kernel;
+use crate::mapping::{self, ValueMap};
+use crate::morphology::{self, Shape, StructuringElement};
+use crate::padding::Padding;
+use crate::pooling::PoolMethod;
+use crate::prelude::*;
+use crate::rank;
+use crate::registry::KernelSpec;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pipeline {
+    /// used in output names, defaults to the file stem
+    #[serde(default)]
+    pub name: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    /// filter with a kernel spec
+    Convolve {
+        kernel: String,
+        #[serde(default = "default_padding")]
+        padding: String,
+        /// cross-correlate instead, without flipping the kernel
+        #[serde(default)]
+        correlate: bool,
+        /// use the fft instead of the direct sum
+        #[serde(default)]
+        fft: bool,
+    },
+    /// pool with a stride of 1
+    Pool {
+        #[serde(default = "default_method")]
+        method: String,
+        #[serde(default = "default_size")]
+        size: usize,
+    },
+    /// median of each window
+    Median {
+        #[serde(default = "default_size")]
+        size: usize,
+        #[serde(default = "default_padding")]
+        padding: String,
+    },
+    /// magnitude of the gradient from two kernels, `sqrt(gx^2 + gy^2)`
+    Gradient {
+        x: String,
+        y: String,
+        #[serde(default = "default_padding")]
+        padding: String,
+    },
+    /// dilate, erode, open, close, gradient, top_hat or black_top_hat with
+    /// a square, disk or cross element
+    Morphology {
+        operation: String,
+        #[serde(default = "default_element")]
+        element: String,
+    },
+    /// rescale linearly so the smallest value is 0 and the largest 1
+    Normalize,
+    /// 1 where the value is at least `value`, otherwise 0
+    Threshold { value: f64 },
+}
+
+fn default_padding() -> String {
+    "reflection".to_string()
+}
+
+fn default_method() -> String {
+    "max".to_string()
+}
+
+fn default_size() -> usize {
+    3
+}
+
+fn default_element() -> String {
+    "square:3".to_string()
+}
+
+/// A step with its kernels built and options parsed, ready to run.
+#[derive(Clone)]
+enum Stage {
+    Convolve {
+        kernel: Kernel,
+        padding: Padding,
+        correlate: bool,
+        fft: bool,
+    },
+    Pool {
+        method: PoolMethod,
+        size: usize,
+    },
+    Median {
+        size: usize,
+        padding: Padding,
+    },
+    Gradient {
+        x: Kernel,
+        y: Kernel,
+        padding: Padding,
+    },
+    Morphology {
+        operation: morphology::Operation,
+        element: StructuringElement,
+    },
+    Normalize,
+    Threshold(f64),
+}
+
+/// A validated pipeline. Running it cannot fail.
+#[derive(Clone)]
+pub struct CompiledPipeline {
+    label: String,
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// read a pipeline, `.json` files as JSON and anything else as TOML
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+
+        let mut pipeline: Pipeline = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        };
+
+        if pipeline.name.is_none() {
+            pipeline.name = path.file_stem().map(|s| s.to_string_lossy().to_string());
+        }
+        Ok(pipeline)
+    }
+
+    /// label used in output names
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| "pipeline".to_string())
+    }
+
+    /// Check every step and build its kernels.
+    pub fn compile(&self) -> Result<CompiledPipeline> {
+        if self.steps.is_empty() {
+            return Err(Error::Pipeline("no steps".to_string()));
+        }
+
+        let mut stages = vec![];
+        for (i, step) in self.steps.iter().enumerate() {
+            let stage = compile_step(step)
+                .map_err(|e| Error::Pipeline(format!("step {}: {}", i + 1, e)))?;
+            stages.push(stage);
+        }
+
+        Ok(CompiledPipeline {
+            label: self.label(),
+            stages,
+        })
+    }
+}
+
+impl CompiledPipeline {
+    /// label of the pipeline it was compiled from
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn run(&self, input: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        let mut img = input.to_owned();
+
+        for stage in &self.stages {
+            img = match stage {
+                Stage::Convolve {
+                    kernel,
+                    padding,
+                    correlate,
+                    fft,
+                } => match (correlate, fft) {
+                    (false, false) => convolve::convolve_2d(&img, kernel, *padding),
+                    (true, false) => convolve::correlate_2d(&img, kernel, *padding),
+                    (false, true) => convolve::fft_convolve_2d(&img, kernel, *padding),
+                    (true, true) => convolve::fft_correlate_2d(&img, kernel, *padding),
+                },
+                Stage::Pool { method, size } => method.pool(&img, *size),
+                Stage::Median { size, padding } => match rank::median_filter(&img, *size, *padding)
+                {
+                    Ok(out) => out,
+                    // the size was checked by `compile`
+                    Err(e) => panic!("{}", e),
+                },
+                Stage::Gradient { x, y, padding } => {
+                    let gx = convolve::convolve_2d(&img, x, *padding);
+                    let gy = convolve::convolve_2d(&img, y, *padding);
+                    gx.iter()
+                        .zip(gy.iter())
+                        .map(|(rx, ry)| {
+                            rx.iter()
+                                .zip(ry.iter())
+                                .map(|(a, b)| (a * a + b * b).sqrt())
+                                .collect()
+                        })
+                        .collect()
+                }
+                Stage::Morphology { operation, element } => operation.apply(&img, element),
+                Stage::Normalize => mapping::apply(&img, ValueMap::MinMax),
+                Stage::Threshold(value) => img
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|v| if v >= value { 1.0 } else { 0.0 })
+                            .collect()
+                    })
+                    .collect(),
+            };
+        }
+
+        img
+    }
+}
+
+/// errors are returned as text so `compile` can say which step failed
+fn compile_step(step: &Step) -> std::result::Result<Stage, String> {
+    let stage = match step {
+        Step::Convolve {
+            kernel,
+            padding,
+            correlate,
+            fft,
+        } => Stage::Convolve {
+            kernel: build_kernel(kernel)?,
+            padding: padding.parse::<Padding>()?,
+            correlate: *correlate,
+            fft: *fft,
+        },
+        Step::Pool { method, size } => Stage::Pool {
+            method: method.parse::<PoolMethod>()?,
+            size: check_size(*size)?,
+        },
+        Step::Median { size, padding } => Stage::Median {
+            size: check_size(*size)?,
+            padding: padding.parse::<Padding>()?,
+        },
+        Step::Gradient { x, y, padding } => Stage::Gradient {
+            x: build_kernel(x)?,
+            y: build_kernel(y)?,
+            padding: padding.parse::<Padding>()?,
+        },
+        Step::Morphology { operation, element } => Stage::Morphology {
+            operation: operation.parse::<morphology::Operation>()?,
+            element: StructuringElement::new(element.parse::<Shape>()?)
+                .map_err(|e| e.to_string())?,
+        },
+        Step::Normalize => Stage::Normalize,
+        Step::Threshold { value } => {
+            if !value.is_finite() {
+                return Err("threshold must be finite".to_string());
+            }
+            Stage::Threshold(*value)
+        }
+    };
+
+    Ok(stage)
+}
+
+fn build_kernel(spec: &str) -> std::result::Result<Kernel, String> {
+    KernelSpec::parse(spec)
+        .and_then(|spec| spec.build())
+        .map_err(|e| e.to_string())
+}
+
+fn check_size(size: usize) -> std::result::Result<usize, String> {
+    if size == 0 {
+        return Err("size must be at least 1".to_string());
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// write `contents` to a temporary file and load it
+    fn load(name: &str, contents: &str) -> Result<Pipeline> {
+        let path = std::env::temp_dir().join(format!("convolutions-pipeline-{}", name));
+        std::fs::write(&path, contents).unwrap();
+        let pipeline = Pipeline::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        pipeline
+    }
+
+    #[test]
+    fn loads_toml_and_json() {
+        let toml = load(
+            "steps.toml",
+            "[[steps]]\nop = \"convolve\"\nkernel = \"blur\"\nfft = true\n\n[[steps]]\nop = \"threshold\"\nvalue = 0.5\n",
+        )
+        .unwrap();
+        assert_eq!(toml.label(), "convolutions-pipeline-steps");
+        assert!(matches!(
+            &toml.steps[0],
+            Step::Convolve { kernel, padding, correlate: false, fft: true }
+                if kernel == "blur" && padding == "reflection"
+        ));
+        assert!(matches!(toml.steps[1], Step::Threshold { value } if value == 0.5));
+
+        let json = load(
+            "steps.json",
+            r#"{"name": "opened", "steps": [{"op": "morphology", "operation": "open"}, {"op": "normalize"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(json.label(), "opened");
+        assert!(matches!(
+            &json.steps[0],
+            Step::Morphology { operation, element } if operation == "open" && element == "square:3"
+        ));
+        assert!(json.compile().is_ok());
+
+        assert!(load("bad.toml", "[[steps]]\nop = \"sharpen_more\"\n").is_err());
+        assert!(load("bad.json", r#"{"steps": [{"op": "threshold"}]}"#).is_err());
+    }
+
+    #[test]
+    fn shipped_pipelines_compile() {
+        for file in ["pipelines/edges.toml", "pipelines/tophat.toml"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(file);
+            let compiled = Pipeline::load(&path).unwrap().compile().unwrap();
+            assert_eq!(Path::new(file).file_stem().unwrap(), compiled.label());
+        }
+    }
+
+    #[test]
+    fn compile_names_the_failing_step() {
+        let step = |s: &str| serde_json::from_str::<Step>(s).unwrap();
+        let good = step(r#"{"op": "normalize"}"#);
+        let bad = [
+            r#"{"op": "convolve", "kernel": "no_such_kernel"}"#,
+            r#"{"op": "convolve", "kernel": "blur", "padding": "mirror"}"#,
+            r#"{"op": "pool", "method": "mode"}"#,
+            r#"{"op": "pool", "size": 0}"#,
+            r#"{"op": "median", "size": 0}"#,
+            r#"{"op": "gradient", "x": "edge_x", "y": "gaussian:0"}"#,
+            r#"{"op": "morphology", "operation": "thin"}"#,
+            r#"{"op": "morphology", "operation": "open", "element": "square:4"}"#,
+        ];
+        for s in bad {
+            let pipeline = Pipeline {
+                name: None,
+                steps: vec![good.clone(), good.clone(), step(s)],
+            };
+            match pipeline.compile() {
+                Err(Error::Pipeline(msg)) => assert!(msg.starts_with("step 3: "), "{}", msg),
+                _ => panic!("{} compiled", s),
+            }
+        }
+
+        let empty = Pipeline {
+            name: None,
+            steps: vec![],
+        };
+        assert!(empty.compile().is_err());
+        assert_eq!(empty.label(), "pipeline");
+    }
+
+    #[test]
+    fn runs_the_steps_in_order() {
+        let img = vec![
+            vec![0., 0., 0., 0., 0.],
+            vec![0., 2., 2., 2., 0.],
+            vec![0., 2., 6., 2., 0.],
+            vec![0., 2., 2., 2., 0.],
+            vec![0., 0., 0., 0., 0.],
+        ];
+        let pipeline = Pipeline {
+            name: Some("peak".to_string()),
+            steps: vec![
+                Step::Pool {
+                    method: "max".to_string(),
+                    size: 3,
+                },
+                Step::Normalize,
+                Step::Threshold { value: 0.5 },
+            ],
+        };
+        let out = pipeline.compile().unwrap().run(&img);
+
+        // only the 3x3 around the peak reaches its value after the max pool
+        let expected = (0..5)
+            .map(|i| {
+                (0..5)
+                    .map(|j| {
+                        if (1..4).contains(&i) && (1..4).contains(&j) {
+                            1.
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        assert_eq!(out, expected);
+
+        let identity = Pipeline {
+            name: None,
+            steps: vec![Step::Convolve {
+                kernel: "identity".to_string(),
+                padding: "zero".to_string(),
+                correlate: false,
+                fft: false,
+            }],
+        };
+        assert_eq!(identity.compile().unwrap().run(&img), img);
+    }
+}
@@ -1,23 +1,365 @@
-use crate::padding;
+use crate::padding::{self, Padding};
+use rand::Rng;
+use std::str::FromStr;
 
-fn pool<P>(input: &Vec<Vec<f64>>, size: usize, mut predicate: P) -> Vec<Vec<f64>>
+/// Window sizes above this use the separable sliding-window path for
+/// max and min pooling instead of scanning every window.
+const SLIDING_THRESHOLD: usize = 3;
+
+/// A borrowed `size` x `size` view into the padded input. Reducers read
+/// the window through this view, so no allocation happens per pixel.
+pub struct Window<'a> {
+    rows: &'a [Vec<f64>],
+    col: usize,
+    size: usize,
+}
+
+impl<'a> Window<'a> {
+    /// side length of the window
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// number of values in the window
+    pub fn len(&self) -> usize {
+        self.size * self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// value at row `i` and column `j` of the window
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        self.rows[i][self.col + j]
+    }
+
+    /// row `i` of the window as a slice of the padded buffer
+    pub fn row(&self, i: usize) -> &'a [f64] {
+        &self.rows[i][self.col..self.col + self.size]
+    }
+
+    /// iterate over the window values in row-major order
+    pub fn iter(&self) -> impl Iterator<Item = f64> + 'a {
+        let (col, size) = (self.col, self.size);
+        self.rows
+            .iter()
+            .flat_map(move |row| row[col..col + size].iter().copied())
+    }
+}
+
+/// Reduces a pooling window down to a single output value. Implemented
+/// for any `FnMut(&Window) -> f64` closure, so both closures and trait
+/// objects can be passed to `pool_with`.
+pub trait Reducer {
+    fn reduce(&mut self, window: &Window) -> f64;
+}
+
+impl<F> Reducer for F
 where
-    P: FnMut(Vec<f64>) -> f64,
+    F: FnMut(&Window) -> f64,
 {
-    let mut padded_input = padding::zero_pad(input, size);
+    fn reduce(&mut self, window: &Window) -> f64 {
+        self(window)
+    }
+}
+
+/// largest value in the window
+pub struct Max;
+
+/// smallest value in the window
+pub struct Min;
+
+/// euclidean norm of the window
+pub struct L2;
+
+/// mean of the window
+pub struct Avg;
+
+impl Reducer for Max {
+    fn reduce(&mut self, window: &Window) -> f64 {
+        window.iter().fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+impl Reducer for Min {
+    fn reduce(&mut self, window: &Window) -> f64 {
+        window.iter().fold(f64::INFINITY, f64::min)
+    }
+}
+
+impl Reducer for L2 {
+    fn reduce(&mut self, window: &Window) -> f64 {
+        window.iter().map(|v| v * v).sum::<f64>().sqrt()
+    }
+}
+
+impl Reducer for Avg {
+    fn reduce(&mut self, window: &Window) -> f64 {
+        window.iter().sum::<f64>() / window.len() as f64
+    }
+}
+
+/// The built-in pooling methods, parsed from their names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolMethod {
+    Max,
+    Min,
+    Avg,
+    L2,
+    Stochastic,
+}
+
+impl PoolMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PoolMethod::Max => "max",
+            PoolMethod::Min => "min",
+            PoolMethod::Avg => "avg",
+            PoolMethod::L2 => "l2",
+            PoolMethod::Stochastic => "stochastic",
+        }
+    }
+
+    pub fn pool(&self, input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+        match self {
+            PoolMethod::Max => max_pool(input, size),
+            PoolMethod::Min => min_pool(input, size),
+            PoolMethod::Avg => avg_pool(input, size),
+            PoolMethod::L2 => l2_pool(input, size),
+            PoolMethod::Stochastic => stochastic_pooling(input, size),
+        }
+    }
+}
+
+impl FromStr for PoolMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(PoolMethod::Max),
+            "min" => Ok(PoolMethod::Min),
+            "avg" => Ok(PoolMethod::Avg),
+            "l2" => Ok(PoolMethod::L2),
+            "stochastic" | "stoch" => Ok(PoolMethod::Stochastic),
+            _ => Err(format!(
+                "unknown pooling method '{}', expected max, min, avg, l2 or stochastic",
+                s
+            )),
+        }
+    }
+}
+
+pub fn max_pool(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    max_pool_padded(input, size, Padding::Zero)
+}
+
+pub fn max_pool_padded(input: &[Vec<f64>], size: usize, mode: Padding) -> Vec<Vec<f64>> {
+    if size > SLIDING_THRESHOLD {
+        return sliding_pool(input, size, mode, f64::max);
+    }
+    pool_padded(input, size, mode, &mut Max)
+}
+
+pub fn min_pool(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    min_pool_padded(input, size, Padding::Zero)
+}
+
+pub fn min_pool_padded(input: &[Vec<f64>], size: usize, mode: Padding) -> Vec<Vec<f64>> {
+    if size > SLIDING_THRESHOLD {
+        return sliding_pool(input, size, mode, f64::min);
+    }
+    pool_padded(input, size, mode, &mut Min)
+}
+
+pub fn l2_pool(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    pool_with(input, size, &mut L2)
+}
+
+pub fn avg_pool(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    pool_with(input, size, &mut Avg)
+}
+
+pub fn stochastic_pooling(input: &[Vec<f64>], size: usize) -> Vec<Vec<f64>> {
+    let mut rng = rand::thread_rng();
+    pool_with(input, size, &mut |w: &Window| {
+        let k = rng.gen_range(0..w.len());
+        w.get(k / size, k % size)
+    })
+}
+
+/// Pool the input with a stride of 1 and zero padding, so the output is
+/// the same size as the input. Each output pixel is `reducer` applied to
+/// the `size` x `size` window centered on it.
+///
+/// For example, a median pool:
+/// pool_with(&img, 3, &mut |w: &Window| {
+///     let mut v = w.iter().collect::<Vec<f64>>();
+///     v.sort_by(|a, b| a.total_cmp(b));
+///     v[v.len() / 2]
+/// })
+pub fn pool_with<R>(input: &[Vec<f64>], size: usize, reducer: &mut R) -> Vec<Vec<f64>>
+where
+    R: Reducer + ?Sized,
+{
+    pool_padded(input, size, Padding::Zero, reducer)
+}
+
+/// Same as `pool_with`, but the input is padded with `mode` instead of
+/// zeros.
+pub fn pool_padded<R>(
+    input: &[Vec<f64>],
+    size: usize,
+    mode: Padding,
+    reducer: &mut R,
+) -> Vec<Vec<f64>>
+where
+    R: Reducer + ?Sized,
+{
+    let padded_input = padding::pad(input, size, mode);
     let mut out = vec![vec![0.; input[0].len()]; input.len()];
 
-    for i in 0..input.len() {
-        for j in 0..input[0].len() {
-            let window = padded_input[i..i + size]
-                .iter()
-                .map(|x| x[j..j + size].iter().map(|n| *n).collect::<Vec<f64>>())
-                .flatten()
-                .collect::<Vec<f64>>();
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, o) in row.iter_mut().enumerate() {
+            // borrow the window straight out of the padded buffer
+            let window = Window {
+                rows: &padded_input[i..i + size],
+                col: j,
+                size,
+            };
+
+            *o = reducer.reduce(&window);
+        }
+    }
+
+    out
+}
+
+/// Max or min pooling with a separable van Herk/Gil-Werman filter. The
+/// extreme of a square window is the extreme of its row-wise extremes,
+/// so a horizontal then a vertical 1d pass give the result in a constant
+/// number of comparisons per pixel, whatever the window size.
+fn sliding_pool(
+    input: &[Vec<f64>],
+    size: usize,
+    mode: Padding,
+    op: fn(f64, f64) -> f64,
+) -> Vec<Vec<f64>> {
+    let padded_input = padding::pad(input, size, mode);
+    let (height, width) = (input.len(), input[0].len());
+
+    // horizontal pass, each padded row shrinks back to the input width
+    let rows = padded_input
+        .iter()
+        .map(|row| sliding_1d(row, size, op))
+        .collect::<Vec<Vec<f64>>>();
 
-            out[i][j] = predicate(window);
+    // vertical pass over each column of the horizontal result
+    let mut out = vec![vec![0.; width]; height];
+    let mut column = vec![0.; rows.len()];
+    for j in 0..width {
+        for i in 0..rows.len() {
+            column[i] = rows[i][j];
+        }
+        let reduced = sliding_1d(&column, size, op);
+        for i in 0..height {
+            out[i][j] = reduced[i];
         }
     }
 
-    return out;
+    out
+}
+
+/// van Herk/Gil-Werman running reduction over every window of `size`
+/// values in `input`, producing `input.len() - size + 1` outputs. The
+/// input is split into blocks of `size`, and each window is covered by
+/// the suffix of one block and the prefix of the next.
+fn sliding_1d(input: &[f64], size: usize, op: fn(f64, f64) -> f64) -> Vec<f64> {
+    let n = input.len();
+    let mut prefix = vec![0.; n];
+    let mut suffix = vec![0.; n];
+
+    for i in 0..n {
+        prefix[i] = if i % size == 0 {
+            input[i]
+        } else {
+            op(prefix[i - 1], input[i])
+        };
+    }
+    for i in (0..n).rev() {
+        suffix[i] = if i == n - 1 || (i + 1) % size == 0 {
+            input[i]
+        } else {
+            op(suffix[i + 1], input[i])
+        };
+    }
+
+    (0..=n - size)
+        .map(|i| op(suffix[i], prefix[i + size - 1]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Vec<Vec<f64>> {
+        (0..5)
+            .map(|i| (0..6).map(|j| ((i * 7 + j * 3) % 11) as f64).collect())
+            .collect()
+    }
+
+    #[test]
+    fn pool_with_takes_closures_and_trait_objects() {
+        let img = image();
+
+        // a closure counting the windows it sees
+        let mut calls = 0;
+        let sum = pool_with(&img, 3, &mut |w: &Window| {
+            calls += 1;
+            w.iter().sum()
+        });
+        assert_eq!(calls, 30);
+        let avg = avg_pool(&img, 3);
+        for (s, a) in sum.iter().flatten().zip(avg.iter().flatten()) {
+            assert!((s / 9. - a).abs() < 1e-12);
+        }
+
+        let mut reducers: Vec<Box<dyn Reducer>> = vec![Box::new(Max), Box::new(Min)];
+        let expected = [max_pool(&img, 3), min_pool(&img, 3)];
+        for (reducer, expected) in reducers.iter_mut().zip(expected.iter()) {
+            let reducer: &mut dyn Reducer = reducer.as_mut();
+            assert_eq!(&pool_with(&img, 3, reducer), expected);
+        }
+    }
+
+    #[test]
+    fn sliding_path_matches_scanning_every_window() {
+        // negative values so the zero padding shows up in the max
+        let img = (0..13)
+            .map(|i| (0..9).map(|j| ((i * 5 + j * 7) % 17) as f64 - 8.).collect())
+            .collect::<Vec<Vec<f64>>>();
+
+        for size in [4, 5, 8, 20] {
+            assert!(size > SLIDING_THRESHOLD);
+            assert_eq!(
+                max_pool(&img, size),
+                pool_padded(&img, size, Padding::Zero, &mut Max)
+            );
+            assert_eq!(
+                min_pool(&img, size),
+                pool_padded(&img, size, Padding::Zero, &mut Min)
+            );
+            for mode in [Padding::Reflection, Padding::Constant(3.)] {
+                assert_eq!(
+                    max_pool_padded(&img, size, mode),
+                    pool_padded(&img, size, mode, &mut Max)
+                );
+                assert_eq!(
+                    min_pool_padded(&img, size, mode),
+                    pool_padded(&img, size, mode, &mut Min)
+                );
+            }
+        }
+    }
 }
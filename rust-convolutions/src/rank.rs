@@ -0,0 +1,293 @@
+//! Rank-order filters
+
+use crate::padding::{self, Padding};
+use crate::prelude::*;
+
+/// Windows with at most this many values are sorted with a sorting
+/// network, larger ones use a selection algorithm.
+const NETWORK_MAX: usize = 25;
+
+/// number of levels used by the histogram filters
+const LEVELS: usize = 256;
+
+pub fn median_filter(input: &[Vec<f64>], size: usize, mode: Padding) -> Result<Vec<Vec<f64>>> {
+    rank_filter(input, size, size * size / 2, mode)
+}
+
+pub fn min_filter(input: &[Vec<f64>], size: usize, mode: Padding) -> Result<Vec<Vec<f64>>> {
+    rank_filter(input, size, 0, mode)
+}
+
+pub fn max_filter(input: &[Vec<f64>], size: usize, mode: Padding) -> Result<Vec<Vec<f64>>> {
+    rank_filter(input, size, (size * size).saturating_sub(1), mode)
+}
+
+/// Filter the input with the `percentile` (0-100) of each window.
+pub fn percentile_filter(
+    input: &[Vec<f64>],
+    size: usize,
+    percentile: f64,
+    mode: Padding,
+) -> Result<Vec<Vec<f64>>> {
+    rank_filter(input, size, percentile_rank(size, percentile), mode)
+}
+
+/// Replace every pixel with the value at position `rank` of its sorted
+/// `size` x `size` neighbourhood, where rank 0 is the minimum and
+/// `size * size - 1` the maximum. The output is the same size as the input.
+pub fn rank_filter(
+    input: &[Vec<f64>],
+    size: usize,
+    rank: usize,
+    mode: Padding,
+) -> Result<Vec<Vec<f64>>> {
+    let n = size * size;
+    check_rank(size, rank)?;
+
+    let padded = padding::pad(input, size, mode);
+    let mut out = vec![vec![0.; input[0].len()]; input.len()];
+
+    // small windows are sorted with a fixed sequence of compare-exchanges
+    let network = if n <= NETWORK_MAX {
+        Some(sorting_network(n))
+    } else {
+        None
+    };
+
+    // one buffer reused for every window
+    let mut window = vec![0.; n];
+
+    for i in 0..input.len() {
+        for j in 0..input[0].len() {
+            for r in 0..size {
+                window[r * size..(r + 1) * size].copy_from_slice(&padded[i + r][j..j + size]);
+            }
+
+            out[i][j] = match &network {
+                Some(pairs) => {
+                    for &(a, b) in pairs {
+                        if window[a] > window[b] {
+                            window.swap(a, b);
+                        }
+                    }
+                    window[rank]
+                }
+                None => *window.select_nth_unstable_by(rank, f64::total_cmp).1,
+            };
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn median_filter_8bit(input: &[Vec<f64>], size: usize, mode: Padding) -> Result<Vec<Vec<f64>>> {
+    rank_filter_8bit(input, size, size * size / 2, mode)
+}
+
+pub fn percentile_filter_8bit(
+    input: &[Vec<f64>],
+    size: usize,
+    percentile: f64,
+    mode: Padding,
+) -> Result<Vec<Vec<f64>>> {
+    rank_filter_8bit(input, size, percentile_rank(size, percentile), mode)
+}
+
+/// Histogram based rank filter for images that came from 8-bit data.
+/// Values in the range 0-1 are quantized to 256 levels, and a running
+/// histogram is slid along each row (Huang's algorithm), so the cost per
+/// pixel grows with the window side rather than the window area. The
+/// output is quantized to the same 256 levels.
+pub fn rank_filter_8bit(
+    input: &[Vec<f64>],
+    size: usize,
+    rank: usize,
+    mode: Padding,
+) -> Result<Vec<Vec<f64>>> {
+    check_rank(size, rank)?;
+
+    // pad then quantize, so every padding mode is available
+    let padded = padding::pad(input, size, mode)
+        .iter()
+        .map(|row| row.iter().map(|v| quantize(*v)).collect::<Vec<usize>>())
+        .collect::<Vec<Vec<usize>>>();
+
+    let (height, width) = (input.len(), input[0].len());
+    let mut out = vec![vec![0.; width]; height];
+
+    for i in 0..height {
+        // build the histogram of the first window in the row
+        let mut hist = [0usize; LEVELS];
+        for row in &padded[i..i + size] {
+            for level in &row[0..size] {
+                hist[*level] += 1;
+            }
+        }
+
+        for j in 0..width {
+            out[i][j] = histogram_rank(&hist, rank) as f64 / (LEVELS - 1) as f64;
+
+            // slide the window one column right
+            if j + 1 < width {
+                for row in &padded[i..i + size] {
+                    hist[row[j]] -= 1;
+                    hist[row[j + size]] += 1;
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// the window must hold at least one value and `rank` must index into it
+fn check_rank(size: usize, rank: usize) -> Result<()> {
+    if size == 0 {
+        return Err(Error::Generic(
+            "rank filter size must be at least 1".to_string(),
+        ));
+    }
+    if rank >= size * size {
+        return Err(Error::Generic(format!(
+            "rank {} is outside a {}x{} window of {} values",
+            rank,
+            size,
+            size,
+            size * size
+        )));
+    }
+    Ok(())
+}
+
+/// the window rank matching a percentile in the range 0-100
+fn percentile_rank(size: usize, percentile: f64) -> usize {
+    let n = size * size;
+    ((percentile.clamp(0., 100.) / 100.) * n.saturating_sub(1) as f64).round() as usize
+}
+
+/// map a 0-1 value onto one of the histogram levels
+fn quantize(value: f64) -> usize {
+    (value.clamp(0., 1.) * (LEVELS - 1) as f64).round() as usize
+}
+
+/// the level holding the value at position `rank` of the histogram
+fn histogram_rank(hist: &[usize; LEVELS], rank: usize) -> usize {
+    let mut count = 0;
+    for (level, c) in hist.iter().enumerate() {
+        count += c;
+        if count > rank {
+            return level;
+        }
+    }
+    LEVELS - 1
+}
+
+/// Batcher's odd-even merge sort as a list of compare-exchange pairs for
+/// `n` values. The network is built for the next power of two, and pairs
+/// touching the missing values are dropped, treating them as +infinity.
+fn sorting_network(n: usize) -> Vec<(usize, usize)> {
+    let len = n.next_power_of_two();
+    let mut pairs = vec![];
+
+    let mut p = 1;
+    while p < len {
+        let mut k = p;
+        while k > 0 {
+            let mut j = k % p;
+            while j + k < len {
+                for i in 0..k.min(len - j - k) {
+                    if (i + j) / (2 * p) == (i + j + k) / (2 * p) && i + j + k < n {
+                        pairs.push((i + j, i + j + k));
+                    }
+                }
+                j += 2 * k;
+            }
+            k /= 2;
+        }
+        p *= 2;
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_ranks_outside_the_window() {
+        let img = vec![vec![0.5; 4]; 4];
+        assert!(rank_filter(&img, 3, 9, Padding::Zero).is_err());
+        assert!(rank_filter_8bit(&img, 3, 9, Padding::Zero).is_err());
+        assert!(max_filter(&img, 0, Padding::Zero).is_err());
+        assert!(percentile_filter(&img, 0, 50., Padding::Zero).is_err());
+        assert!(rank_filter(&img, 3, 8, Padding::Zero).is_ok());
+    }
+
+    #[test]
+    fn histogram_matches_sorting_on_8bit_input() {
+        // values already on the 256 levels, so quantizing loses nothing
+        let img = (0..11)
+            .map(|i| {
+                (0..14)
+                    .map(|j| ((i * 37 + j * 91) % 256) as f64 / 255.)
+                    .collect()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        let modes = [
+            Padding::Zero,
+            Padding::Reflection,
+            Padding::Constant(200. / 255.),
+        ];
+        for size in [1, 3, 4, 7] {
+            let n = size * size;
+            for rank in [0, n / 3, n / 2, n - 1] {
+                for mode in modes {
+                    assert_eq!(
+                        rank_filter_8bit(&img, size, rank, mode).unwrap(),
+                        rank_filter(&img, size, rank, mode).unwrap(),
+                        "size {} rank {} {:?}",
+                        size,
+                        rank,
+                        mode
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn networks_sort_every_0_1_input() {
+        // by the 0-1 principle a network sorting every input of zeros and
+        // ones sorts everything. Each u64 wire holds 64 inputs at once, bit
+        // `l` of input `base + l` being the value on that wire.
+        for n in 1..=NETWORK_MAX {
+            let pairs = sorting_network(n);
+            let lanes =
+                |w: usize| -> u64 { (0..64).fold(0, |acc, l| acc | ((l as u64 >> w) & 1) << l) };
+            let low = (0..n.min(6)).map(lanes).collect::<Vec<u64>>();
+
+            for base in (0..1usize << n).step_by(64) {
+                let mut wires = (0..n)
+                    .map(|w| {
+                        if w < 6 {
+                            low[w]
+                        } else if (base >> w) & 1 == 1 {
+                            u64::MAX
+                        } else {
+                            0
+                        }
+                    })
+                    .collect::<Vec<u64>>();
+                for &(a, b) in &pairs {
+                    let (x, y) = (wires[a], wires[b]);
+                    wires[a] = x & y;
+                    wires[b] = x | y;
+                }
+                for w in 1..n {
+                    assert_eq!(wires[w - 1] & !wires[w], 0, "n = {}", n);
+                }
+            }
+        }
+    }
+}
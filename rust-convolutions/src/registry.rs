@@ -0,0 +1,392 @@
+//! Named kernels built from string specs
+//!
+//! A spec is a kernel name followed by its parameters, separated by
+//! colons, such as `gaussian:31:5.0` or `motion_blur:15:0.785`. Missing
+//! trailing parameters take their defaults, so `gaussian` alone is a 5x5
+//! gaussian with a sigma of 1. The spec `file:<path>` loads a kernel with
+//! `kernel_file::load_kernel`.
+
+use crate::kernel::{Kernel, KernelConstructors};
+use crate::kernel_file;
+use crate::prelude::*;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+pub struct Param {
+    pub name: &'static str,
+    pub default: f64,
+    /// whether the parameter must be a whole number
+    pub integer: bool,
+    /// smallest value accepted
+    pub min: f64,
+    /// whether `min` itself is refused, for values that must be positive
+    pub exclusive: bool,
+    /// largest value accepted
+    pub max: f64,
+}
+
+pub struct KernelEntry {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: &'static [Param],
+    build: fn(&[f64]) -> Result<Kernel>,
+}
+
+/// largest kernel side length accepted from a spec
+const MAX_SIZE: f64 = 1001.;
+
+/// any finite number, such as an angle
+const fn param(name: &'static str, default: f64) -> Param {
+    Param {
+        name,
+        default,
+        integer: false,
+        min: f64::NEG_INFINITY,
+        exclusive: false,
+        max: f64::INFINITY,
+    }
+}
+
+/// a number greater than 0, such as a sigma or a wavelength
+const fn positive(name: &'static str, default: f64) -> Param {
+    Param {
+        name,
+        default,
+        integer: false,
+        min: 0.,
+        exclusive: true,
+        max: f64::INFINITY,
+    }
+}
+
+/// a kernel side length, from 1 to `MAX_SIZE`
+const fn size(name: &'static str, default: f64) -> Param {
+    Param {
+        name,
+        default,
+        integer: true,
+        min: 1.,
+        exclusive: false,
+        max: MAX_SIZE,
+    }
+}
+
+/// a radius that may be zero, giving a side length of at most `MAX_SIZE`
+const fn radius(name: &'static str, default: f64) -> Param {
+    Param {
+        name,
+        default,
+        integer: true,
+        min: 0.,
+        exclusive: false,
+        max: (MAX_SIZE - 1.) / 2.,
+    }
+}
+
+const KERNELS: &[KernelEntry] = &[
+    KernelEntry {
+        name: "identity",
+        description: "3x3 identity",
+        params: &[],
+        build: |_| Ok(Kernel::identity()),
+    },
+    KernelEntry {
+        name: "blur",
+        description: "3x3 box blur",
+        params: &[],
+        build: |_| Ok(Kernel::blur()),
+    },
+    KernelEntry {
+        name: "box_blur",
+        description: "box blur of any size",
+        params: &[size("size", 3.)],
+        build: |p| Kernel::box_blur(p[0] as usize),
+    },
+    KernelEntry {
+        name: "disk",
+        description: "flat circular blur",
+        params: &[radius("radius", 2.)],
+        build: |p| Ok(Kernel::disk(p[0] as usize)),
+    },
+    KernelEntry {
+        name: "gaussian",
+        description: "normalized gaussian blur",
+        params: &[size("size", 5.), positive("sigma", 1.)],
+        build: |p| Kernel::gaussian(p[0] as usize, p[1]),
+    },
+    KernelEntry {
+        name: "motion_blur",
+        description: "blur along a line at an angle in radians",
+        params: &[size("size", 9.), param("angle", 0.)],
+        build: |p| Kernel::motion_blur(p[0] as usize, p[1]),
+    },
+    KernelEntry {
+        name: "edge_x",
+        description: "sobel horizontal gradient",
+        params: &[],
+        build: |_| Ok(Kernel::edge_x()),
+    },
+    KernelEntry {
+        name: "edge_y",
+        description: "sobel vertical gradient",
+        params: &[],
+        build: |_| Ok(Kernel::edge_y()),
+    },
+    KernelEntry {
+        name: "edge_all",
+        description: "3x3 laplacian",
+        params: &[],
+        build: |_| Ok(Kernel::edge_all()),
+    },
+    KernelEntry {
+        name: "scharr_x",
+        description: "scharr horizontal gradient",
+        params: &[],
+        build: |_| Ok(Kernel::scharr_x()),
+    },
+    KernelEntry {
+        name: "scharr_y",
+        description: "scharr vertical gradient",
+        params: &[],
+        build: |_| Ok(Kernel::scharr_y()),
+    },
+    KernelEntry {
+        name: "prewitt_x",
+        description: "prewitt horizontal gradient",
+        params: &[],
+        build: |_| Ok(Kernel::prewitt_x()),
+    },
+    KernelEntry {
+        name: "prewitt_y",
+        description: "prewitt vertical gradient",
+        params: &[],
+        build: |_| Ok(Kernel::prewitt_y()),
+    },
+    KernelEntry {
+        name: "roberts_x",
+        description: "roberts cross diagonal gradient",
+        params: &[],
+        build: |_| Ok(Kernel::roberts_x()),
+    },
+    KernelEntry {
+        name: "roberts_y",
+        description: "roberts cross anti-diagonal gradient",
+        params: &[],
+        build: |_| Ok(Kernel::roberts_y()),
+    },
+    KernelEntry {
+        name: "laplacian_of_gaussian",
+        description: "zero-sum laplacian of gaussian",
+        params: &[size("size", 9.), positive("sigma", 1.4)],
+        build: |p| Kernel::laplacian_of_gaussian(p[0] as usize, p[1]),
+    },
+    KernelEntry {
+        name: "difference_of_gaussians",
+        description: "difference of two gaussians",
+        params: &[
+            size("size", 9.),
+            positive("sigma1", 1.),
+            positive("sigma2", 2.),
+        ],
+        build: |p| Kernel::difference_of_gaussians(p[0] as usize, p[1], p[2]),
+    },
+    KernelEntry {
+        name: "gabor",
+        description: "gabor filter, angles and phase in radians",
+        params: &[
+            size("size", 21.),
+            positive("sigma", 4.),
+            param("theta", 0.),
+            positive("wavelength", 10.),
+            param("phase", 0.),
+            param("gamma", 0.5),
+        ],
+        build: |p| Kernel::gabor(p[0] as usize, p[1], p[2], p[3], p[4], p[5]),
+    },
+    KernelEntry {
+        name: "sharpen",
+        description: "3x3 sharpen",
+        params: &[],
+        build: |_| Ok(Kernel::sharpen()),
+    },
+    KernelEntry {
+        name: "unsharp_mask",
+        description: "gaussian unsharp mask",
+        params: &[size("size", 5.), positive("sigma", 1.), param("amount", 1.)],
+        build: |p| Kernel::unsharp_mask(p[0] as usize, p[1], p[2]),
+    },
+    KernelEntry {
+        name: "emboss",
+        description: "3x3 emboss",
+        params: &[],
+        build: |_| Ok(Kernel::emboss()),
+    },
+    KernelEntry {
+        name: "edge_enhance",
+        description: "7x7 edge enhance",
+        params: &[],
+        build: |_| Ok(Kernel::edge_enhance()),
+    },
+];
+
+/// every kernel available by name
+pub fn kernels() -> &'static [KernelEntry] {
+    KERNELS
+}
+
+pub fn find(name: &str) -> Option<&'static KernelEntry> {
+    KERNELS.iter().find(|k| k.name == name)
+}
+
+/// A parsed kernel spec. Converting it back to a string gives the
+/// canonical form with every parameter filled in, which is stable enough
+/// to use in output names and logs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KernelSpec {
+    Named { name: String, params: Vec<f64> },
+    File(String),
+}
+
+impl KernelSpec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if let Some(path) = spec.strip_prefix("file:") {
+            return Ok(KernelSpec::File(path.to_string()));
+        }
+
+        let mut parts = spec.split(':');
+        let name = parts.next().unwrap_or_default();
+        let entry = find(name)
+            .ok_or_else(|| Error::KernelSpec(format!("unknown kernel '{}' in '{}'", name, spec)))?;
+
+        let values = parts.collect::<Vec<&str>>();
+        if values.len() > entry.params.len() {
+            return Err(Error::KernelSpec(format!(
+                "'{}' takes at most {} parameters, got {}",
+                name,
+                entry.params.len(),
+                values.len()
+            )));
+        }
+
+        let mut params = vec![];
+        for (i, param) in entry.params.iter().enumerate() {
+            let value = match values.get(i) {
+                Some(v) => v.parse::<f64>().map_err(|_| {
+                    Error::KernelSpec(format!("{} '{}' is not a number", param.name, v))
+                })?,
+                None => param.default,
+            };
+            if param.integer && value.fract() != 0. {
+                return Err(Error::KernelSpec(format!(
+                    "{} must be a whole number, got {}",
+                    param.name, value
+                )));
+            }
+            if !value.is_finite() {
+                return Err(Error::KernelSpec(format!(
+                    "{} must be finite, got {}",
+                    param.name, value
+                )));
+            }
+            if param.exclusive && value <= param.min {
+                return Err(Error::KernelSpec(format!(
+                    "{} must be greater than {}, got {}",
+                    param.name, param.min, value
+                )));
+            }
+            if value < param.min || value > param.max {
+                return Err(Error::KernelSpec(format!(
+                    "{} must be from {} to {}, got {}",
+                    param.name, param.min, param.max, value
+                )));
+            }
+            params.push(value);
+        }
+
+        Ok(KernelSpec::Named {
+            name: name.to_string(),
+            params,
+        })
+    }
+
+    pub fn build(&self) -> Result<Kernel> {
+        match self {
+            KernelSpec::Named { name, params } => {
+                let entry = find(name)
+                    .ok_or_else(|| Error::KernelSpec(format!("unknown kernel '{}'", name)))?;
+                (entry.build)(params)
+            }
+            KernelSpec::File(path) => kernel_file::load_kernel(path),
+        }
+    }
+
+    /// The spec in a form safe for file names, colons become dashes and a
+    /// file spec is reduced to the file stem.
+    pub fn label(&self) -> String {
+        match self {
+            KernelSpec::File(path) => Path::new(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| "file".to_string()),
+            KernelSpec::Named { .. } => self.to_string().replace(':', "-"),
+        }
+    }
+}
+
+impl FromStr for KernelSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        KernelSpec::parse(s)
+    }
+}
+
+impl fmt::Display for KernelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelSpec::Named { name, params } => {
+                write!(f, "{}", name)?;
+                for p in params {
+                    write!(f, ":{}", p)?;
+                }
+                Ok(())
+            }
+            KernelSpec::File(path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_values_that_build_bad_kernels() {
+        for spec in [
+            "gaussian:31:0",
+            "gaussian:5:-1",
+            "gaussian:1000000000",
+            "gabor:21:4:0:0",
+            "difference_of_gaussians:9:1:0",
+            "laplacian_of_gaussian:9:0",
+            "disk:100000",
+            "motion_blur:9:inf",
+        ] {
+            assert!(KernelSpec::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn every_default_builds_a_finite_kernel() {
+        for entry in kernels() {
+            let k = KernelSpec::parse(entry.name).unwrap().build().unwrap();
+            assert!(
+                k.values().iter().flatten().all(|v| v.is_finite()),
+                "{}",
+                entry.name
+            );
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! Reading and writing 1d signals
+//!
+//! Text signals hold numbers separated by whitespace, commas or new lines,
+//! with `#` starting a comment. WAV files can have any number of channels
+//! of 8 to 32 bit integer or 32 bit float samples, integers are scaled to
+//! -1 to 1.
+
+use crate::conv1d::OutputMode;
+use crate::fir::FirDesign;
+use crate::prelude::*;
+use crate::streaming::{BlockMethod, StreamingConvolver};
+use hound::{WavReader, WavSpec, WavWriter};
+use std::io::{Seek, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// sample rate given to text signals written as WAV
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// frames read at a time by `stream_wav`
+const STREAM_CHUNK: usize = 65536;
+
+/// how samples are stored in a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// signed integers of 8, 16, 24 or 32 bits
+    Int(u16),
+    Float,
+}
+
+/// A signal with one or more channels of equal length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub format: SampleFormat,
+    pub channels: Vec<Vec<f64>>,
+}
+
+/// Parses `i8`, `i16`, `i24`, `i32` and `f32`.
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "i8" => Ok(SampleFormat::Int(8)),
+            "i16" => Ok(SampleFormat::Int(16)),
+            "i24" => Ok(SampleFormat::Int(24)),
+            "i32" => Ok(SampleFormat::Int(32)),
+            "f32" => Ok(SampleFormat::Float),
+            _ => Err(format!(
+                "unknown sample format '{}', expected i8, i16, i24, i32 or f32",
+                s
+            )),
+        }
+    }
+}
+
+/// Where the kernel of a 1d filter comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalKernel {
+    /// a text or WAV file, such as a reverb impulse response
+    File(PathBuf),
+    /// a designed filter, see `fir`
+    Fir(FirDesign),
+}
+
+/// Parses `file:<path>` and the filters of `FirDesign`.
+impl FromStr for SignalKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.strip_prefix("file:") {
+            Some(path) if !path.is_empty() => Ok(SignalKernel::File(PathBuf::from(path))),
+            Some(_) => Err("file:<path> needs a path".to_string()),
+            None => s.parse::<FirDesign>().map(SignalKernel::Fir),
+        }
+    }
+}
+
+impl SignalKernel {
+    /// the kernel, with one channel or one per channel of the signal
+    pub fn load(&self) -> Result<Audio> {
+        match self {
+            SignalKernel::File(path) => read_audio(path),
+            SignalKernel::Fir(design) => Ok(Audio::mono(design.build()?)),
+        }
+    }
+
+    /// Reject an impulse response recorded at another sample rate than
+    /// the WAV `input`, which would stretch or squeeze it in time. Text
+    /// signals and designed filters have no sample rate of their own.
+    pub fn check_sample_rate(&self, kernel: &Audio, input: impl AsRef<Path>) -> Result<()> {
+        let input = input.as_ref();
+        let path = match self {
+            SignalKernel::File(path) if is_wav(path) && is_wav(input) => path,
+            _ => return Ok(()),
+        };
+
+        let rate = WavReader::open(input)?.spec().sample_rate;
+        if rate != kernel.sample_rate {
+            return Err(Error::Generic(format!(
+                "{} is at {} Hz but {} is at {} Hz, resample one of them first",
+                path.display(),
+                kernel.sample_rate,
+                input.display(),
+                rate
+            )));
+        }
+        Ok(())
+    }
+
+    /// label used in output names
+    pub fn label(&self) -> String {
+        match self {
+            SignalKernel::File(path) => path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| "file".to_string()),
+            SignalKernel::Fir(design) => design.label(),
+        }
+    }
+}
+
+impl Audio {
+    /// one channel at the default sample rate, stored as 32 bit floats
+    pub fn mono(samples: Vec<f64>) -> Self {
+        Audio {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            format: SampleFormat::Float,
+            channels: vec![samples],
+        }
+    }
+
+    /// samples in each channel
+    pub fn len(&self) -> usize {
+        self.channels.first().map(|c| c.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the average of the channels
+    pub fn mixdown(&self) -> Vec<f64> {
+        let count = self.channels.len() as f64;
+        (0..self.len())
+            .map(|i| self.channels.iter().map(|c| c[i]).sum::<f64>() / count)
+            .collect()
+    }
+}
+
+/// whether `path` is read and written as WAV rather than text
+pub fn is_wav(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("wav"))
+        .unwrap_or(false)
+}
+
+/// read a WAV file, or a text file as one channel
+pub fn read_audio(path: impl AsRef<Path>) -> Result<Audio> {
+    let path = path.as_ref();
+    if !is_wav(path) {
+        return Ok(Audio::mono(read_signal(path)?));
+    }
+
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let count = spec.channels as usize;
+
+    let (format, samples) = match spec.sample_format {
+        hound::SampleFormat::Float => (
+            SampleFormat::Float,
+            reader
+                .samples::<f32>()
+                .map(|s| s.map(|v| v as f64))
+                .collect::<std::result::Result<Vec<f64>, hound::Error>>()?,
+        ),
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f64;
+            (
+                SampleFormat::Int(spec.bits_per_sample),
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|v| v as f64 / scale))
+                    .collect::<std::result::Result<Vec<f64>, hound::Error>>()?,
+            )
+        }
+    };
+
+    // the samples are interleaved, one from each channel in turn
+    let channels = (0..count)
+        .map(|c| samples.iter().skip(c).step_by(count).copied().collect())
+        .collect();
+
+    Ok(Audio {
+        sample_rate: spec.sample_rate,
+        format,
+        channels,
+    })
+}
+
+/// Write a WAV file, or a text file for a single channel. Integer samples
+/// are clipped to -1 to 1.
+pub fn write_audio(path: impl AsRef<Path>, audio: &Audio) -> Result<()> {
+    let path = path.as_ref();
+    if !is_wav(path) {
+        if audio.channels.len() != 1 {
+            return Err(Error::Generic(format!(
+                "text signals hold one channel, not {}, write a .wav instead",
+                audio.channels.len()
+            )));
+        }
+        return write_signal(path, &audio.channels[0]);
+    }
+
+    let (bits_per_sample, sample_format) = match audio.format {
+        SampleFormat::Int(bits) => (bits, hound::SampleFormat::Int),
+        SampleFormat::Float => (32, hound::SampleFormat::Float),
+    };
+    let spec = WavSpec {
+        channels: audio.channels.len() as u16,
+        sample_rate: audio.sample_rate,
+        bits_per_sample,
+        sample_format,
+    };
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for i in 0..audio.len() {
+        for channel in &audio.channels {
+            write_sample(&mut writer, audio.format, channel[i])?;
+        }
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Filter a WAV file into another in chunks, through a
+/// `StreamingConvolver` per channel, so neither file is held in memory.
+/// Like `run_signal`, `kernel` filters each channel with its own channel
+/// when the counts match and with its mixdown otherwise. The signal is
+/// taken to be zero past its ends. Returns the number of channels and the
+/// samples written to each.
+pub fn stream_wav(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    kernel: &Audio,
+    mode: OutputMode,
+    method: BlockMethod,
+    gain: f64,
+    format: Option<SampleFormat>,
+) -> Result<(usize, usize)> {
+    let mut reader = WavReader::open(input)?;
+    let spec = reader.spec();
+    let count = spec.channels as usize;
+    let n = reader.duration() as usize;
+
+    let mono = kernel.mixdown();
+    let mut convolvers = (0..count)
+        .map(|c| {
+            let k = if kernel.channels.len() == count {
+                &kernel.channels[c]
+            } else {
+                &mono
+            };
+            StreamingConvolver::new(k, method, None)
+        })
+        .collect::<Result<Vec<StreamingConvolver>>>()?;
+
+    // the outputs to keep, out of the `n + m - 1` of the full convolution
+    let m = kernel.len();
+    let keep = match mode {
+        OutputMode::Full => 0..n + m - 1,
+        OutputMode::Same => m / 2..m / 2 + n,
+        OutputMode::Valid => m - 1..n.max(m - 1),
+    };
+
+    let format = format.unwrap_or(match spec.sample_format {
+        hound::SampleFormat::Float => SampleFormat::Float,
+        hound::SampleFormat::Int => SampleFormat::Int(spec.bits_per_sample),
+    });
+    let (bits_per_sample, sample_format) = match format {
+        SampleFormat::Int(bits) => (bits, hound::SampleFormat::Int),
+        SampleFormat::Float => (32, hound::SampleFormat::Float),
+    };
+    let mut writer = WavWriter::create(
+        output,
+        WavSpec {
+            bits_per_sample,
+            sample_format,
+            ..spec
+        },
+    )?;
+
+    let scale = (1u64 << (spec.bits_per_sample - 1)) as f64;
+    let mut samples: Box<dyn Iterator<Item = hound::Result<f64>>> = match spec.sample_format {
+        hound::SampleFormat::Float => {
+            Box::new(reader.samples::<f32>().map(|s| s.map(|v| v as f64)))
+        }
+        hound::SampleFormat::Int => Box::new(
+            reader
+                .samples::<i32>()
+                .map(move |s| s.map(|v| v as f64 / scale)),
+        ),
+    };
+
+    let mut position = 0;
+    loop {
+        // deinterleave the next chunk
+        let mut chunk = vec![Vec::with_capacity(STREAM_CHUNK); count];
+        for (i, sample) in samples.by_ref().take(STREAM_CHUNK * count).enumerate() {
+            chunk[i % count].push(sample? * gain);
+        }
+        if chunk[0].is_empty() {
+            break;
+        }
+
+        let out = convolvers
+            .iter_mut()
+            .zip(chunk.iter())
+            .map(|(convolver, x)| convolver.process(x))
+            .collect::<Vec<Vec<f64>>>();
+        position = write_frames(&mut writer, format, &out, position, &keep)?;
+    }
+
+    let out = convolvers
+        .iter_mut()
+        .map(|convolver| convolver.finish())
+        .collect::<Vec<Vec<f64>>>();
+    write_frames(&mut writer, format, &out, position, &keep)?;
+    writer.finalize()?;
+
+    Ok((count, keep.len()))
+}
+
+/// write the frames of `channels`, which start at output `position`, that
+/// fall in `keep`, returning the position after them
+fn write_frames<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    format: SampleFormat,
+    channels: &[Vec<f64>],
+    position: usize,
+    keep: &Range<usize>,
+) -> Result<usize> {
+    let len = channels[0].len();
+    for i in 0..len {
+        if keep.contains(&(position + i)) {
+            for channel in channels {
+                write_sample(writer, format, channel[i])?;
+            }
+        }
+    }
+    Ok(position + len)
+}
+
+/// write one sample, clipping integers to -1 to 1
+fn write_sample<W: Write + Seek>(
+    writer: &mut WavWriter<W>,
+    format: SampleFormat,
+    v: f64,
+) -> Result<()> {
+    match format {
+        SampleFormat::Float => writer.write_sample(v as f32)?,
+        SampleFormat::Int(bits) => {
+            let max = ((1u64 << (bits - 1)) - 1) as f64;
+            let v = (v * (max + 1.)).round().clamp(-max - 1., max);
+            writer.write_sample(v as i32)?
+        }
+    }
+    Ok(())
+}
+
+/// read a text file of samples
+pub fn read_signal(path: impl AsRef<Path>) -> Result<Vec<f64>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+
+    let mut samples = vec![];
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        for value in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if value.is_empty() {
+                continue;
+            }
+            let v = value.parse::<f64>().map_err(|_| {
+                Error::Generic(format!(
+                    "{} line {}: '{}' is not a number",
+                    path.display(),
+                    n + 1,
+                    value
+                ))
+            })?;
+            samples.push(v);
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(Error::Generic(format!("{} has no samples", path.display())));
+    }
+    Ok(samples)
+}
+
+/// write samples as text, one per line
+pub fn write_signal(path: impl AsRef<Path>, samples: &[f64]) -> Result<()> {
+    let contents = samples
+        .iter()
+        .map(|v| format!("{}\n", v))
+        .collect::<String>();
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conv1d::{self, Method};
+    use crate::padding::Padding;
+
+    #[test]
+    fn streaming_matches_filtering_in_memory() {
+        let dir = std::env::temp_dir();
+        let (input, output) = (dir.join("stream-in.wav"), dir.join("stream-out.wav"));
+        let audio = Audio {
+            sample_rate: 8000,
+            format: SampleFormat::Float,
+            channels: vec![
+                (0..3000).map(|i| ((i as f64) * 0.05).sin() * 0.5).collect(),
+                (0..3000).map(|i| ((i % 17) as f64 - 8.) / 16.).collect(),
+            ],
+        };
+        write_audio(&input, &audio).unwrap();
+        let kernel = Audio::mono(vec![0.25, 0.5, 0.25, -0.125]);
+
+        for mode in [OutputMode::Full, OutputMode::Same, OutputMode::Valid] {
+            for method in [BlockMethod::OverlapAdd, BlockMethod::OverlapSave] {
+                stream_wav(&input, &output, &kernel, mode, method, 2., None).unwrap();
+                let streamed = read_audio(&output).unwrap();
+                for (x, y) in audio.channels.iter().zip(streamed.channels.iter()) {
+                    let expected = conv1d::convolve(
+                        x,
+                        &kernel.channels[0],
+                        mode,
+                        Padding::Zero,
+                        Method::Direct,
+                    );
+                    assert_eq!(y.len(), expected.len());
+                    for (a, b) in y.iter().zip(expected.iter()) {
+                        assert!((a - 2. * b).abs() < 1e-6);
+                    }
+                }
+            }
+        }
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    /// `channels` channels of samples that every format stores exactly
+    fn exact_audio(format: SampleFormat, channels: usize) -> Audio {
+        let bits = match format {
+            SampleFormat::Int(bits) => bits,
+            SampleFormat::Float => 24,
+        };
+        let scale = (1u64 << (bits - 1)) as f64;
+        let channels = (0..channels)
+            .map(|c| {
+                (0..500)
+                    .map(|i| {
+                        let v = ((i * (c + 3)) as f64 * 0.07).sin() * 0.9;
+                        (v * scale).round() / scale
+                    })
+                    .collect()
+            })
+            .collect();
+        Audio {
+            sample_rate: 22050,
+            format,
+            channels,
+        }
+    }
+
+    #[test]
+    fn wav_round_trips() {
+        let path = std::env::temp_dir().join("convolutions-round-trip.wav");
+        let formats = [
+            SampleFormat::Int(8),
+            SampleFormat::Int(16),
+            SampleFormat::Int(24),
+            SampleFormat::Int(32),
+            SampleFormat::Float,
+        ];
+        for format in formats {
+            for channels in [1, 2, 5] {
+                let audio = exact_audio(format, channels);
+                write_audio(&path, &audio).unwrap();
+                assert_eq!(read_audio(&path).unwrap(), audio, "{:?}", format);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn integer_samples_are_clipped() {
+        let path = std::env::temp_dir().join("convolutions-clipped.wav");
+        let mut audio = Audio::mono(vec![-3., -1., 0.5, 1., 2.]);
+        audio.format = SampleFormat::Int(16);
+        write_audio(&path, &audio).unwrap();
+        let read = read_audio(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.channels[0][..3], [-1., -1., 0.5]);
+        assert_eq!(read.channels[0][3..], [32767. / 32768.; 2]);
+    }
+
+    #[test]
+    fn text_signals_are_one_channel() {
+        let path = std::env::temp_dir().join("convolutions-signal.txt");
+        let audio = Audio::mono(vec![0.1, -2.5, 1. / 3., 7.]);
+        write_audio(&path, &audio).unwrap();
+        assert_eq!(read_audio(&path).unwrap(), audio);
+        assert!(write_audio(&path, &exact_audio(SampleFormat::Float, 2)).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn impulse_responses_must_share_the_sample_rate() {
+        let dir = std::env::temp_dir();
+        let (input, ir) = (dir.join("rate-in.wav"), dir.join("rate-ir.wav"));
+        write_audio(&input, &exact_audio(SampleFormat::Int(16), 1)).unwrap();
+
+        let mut kernel = Audio::mono(vec![0.5, 0.25]);
+        let file = SignalKernel::File(ir.clone());
+        for (rate, ok) in [(22050, true), (48000, false)] {
+            kernel.sample_rate = rate;
+            write_audio(&ir, &kernel).unwrap();
+            let loaded = file.load().unwrap();
+            assert_eq!(file.check_sample_rate(&loaded, &input).is_ok(), ok);
+        }
+
+        // designed filters have no sample rate to compare
+        let fir = "lowpass:11:0.1".parse::<SignalKernel>().unwrap();
+        let taps = fir.load().unwrap();
+        assert!(fir.check_sample_rate(&taps, &input).is_ok());
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&ir).unwrap();
+    }
+}
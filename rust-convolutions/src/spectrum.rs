@@ -0,0 +1,75 @@
+//! Viewing the 2d fourier spectrum of an image
+//!
+//! The spectrum is shifted so the zero frequency sits in the middle of the
+//! image, with frequencies growing outwards. The magnitude spans many
+//! orders of magnitude, so it is shown on a log scale.
+
+use crate::dft::{self, Backend};
+use num::complex::Complex;
+use std::f64::consts::PI;
+
+/// 2d spectrum of `input`. The handwritten backend pads each side to the
+/// next power of 2, rustfft keeps the image size.
+pub fn spectrum(input: &[Vec<f64>], backend: Backend) -> Vec<Vec<Complex<f64>>> {
+    match backend {
+        Backend::Rustfft => {
+            let complex = input
+                .iter()
+                .map(|row| row.iter().map(|v| Complex::new(*v, 0.)).collect())
+                .collect::<Vec<Vec<Complex<f64>>>>();
+            dft::fft_2d(&complex)
+        }
+        Backend::Handwritten => dft::fft_2d_padded(input),
+    }
+}
+
+/// Move the zero frequency from the top left corner to the centre,
+/// at `(rows / 2, cols / 2)`.
+pub fn fftshift<T: Clone>(x: &[Vec<T>]) -> Vec<Vec<T>> {
+    roll(x, x.len() / 2, x[0].len() / 2)
+}
+
+/// inverse of `fftshift`, differing from it only for odd sizes
+pub fn ifftshift<T: Clone>(x: &[Vec<T>]) -> Vec<Vec<T>> {
+    roll(x, x.len().div_ceil(2), x[0].len().div_ceil(2))
+}
+
+/// `ln(1 + |F|)` of the centred spectrum, divided by its largest value so
+/// the result is 0-1
+pub fn log_magnitude(spectrum: &[Vec<Complex<f64>>]) -> Vec<Vec<f64>> {
+    let shifted = fftshift(spectrum);
+    let magnitude = shifted
+        .iter()
+        .map(|row| row.iter().map(|v| v.norm().ln_1p()).collect())
+        .collect::<Vec<Vec<f64>>>();
+
+    let max = magnitude.iter().flatten().cloned().fold(0., f64::max);
+    if max == 0. {
+        return magnitude;
+    }
+    magnitude
+        .iter()
+        .map(|row| row.iter().map(|v| v / max).collect())
+        .collect()
+}
+
+/// phase of the centred spectrum, with -pi to pi mapped to 0-1
+pub fn phase(spectrum: &[Vec<Complex<f64>>]) -> Vec<Vec<f64>> {
+    fftshift(spectrum)
+        .iter()
+        .map(|row| row.iter().map(|v| (v.arg() + PI) / (2. * PI)).collect())
+        .collect()
+}
+
+/// shift every row down by `dy` and every column right by `dx`, wrapping
+fn roll<T: Clone>(x: &[Vec<T>], dy: usize, dx: usize) -> Vec<Vec<T>> {
+    let (rows, cols) = (x.len(), x[0].len());
+    (0..rows)
+        .map(|i| {
+            let row = &x[(i + rows - dy % rows) % rows];
+            (0..cols)
+                .map(|j| row[(j + cols - dx % cols) % cols].clone())
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,190 @@
+//! Short-time fourier transform of 1d signals
+//!
+//! The signal is cut into overlapping frames `hop` samples apart, each
+//! frame is windowed and transformed, giving how the spectrum changes
+//! over time. Frames are centred on their sample, so the signal is padded
+//! with half a window of zeros at each end.
+
+use crate::prelude::*;
+use crate::windowing::{Correction, WindowFunction};
+use num::complex::Complex;
+use num::Zero;
+use rustfft::FftPlanner;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StftConfig {
+    pub window: WindowFunction,
+    /// samples in each frame
+    pub window_size: usize,
+    /// samples between the starts of frames
+    pub hop: usize,
+    /// length of each fft, at least `window_size`, the frame is padded
+    /// with zeros to fit
+    pub fft_size: usize,
+    /// scales the frames to undo the window, `istft` divides it out again
+    pub correction: Correction,
+}
+
+impl StftConfig {
+    /// Hann window of `window_size` with a hop of a quarter window, no
+    /// extra padding and no correction.
+    pub fn new(window_size: usize) -> Self {
+        StftConfig {
+            window: WindowFunction::Hann,
+            window_size,
+            hop: (window_size / 4).max(1),
+            fft_size: window_size,
+            correction: Correction::None,
+        }
+    }
+
+    /// check the sizes fit together
+    pub fn validate(&self) -> Result<()> {
+        if self.window_size == 0 {
+            return Err(Error::Generic("window size must be at least 1".to_string()));
+        }
+        if self.hop == 0 || self.hop > self.window_size {
+            return Err(Error::Generic(
+                "hop must be between 1 and the window size".to_string(),
+            ));
+        }
+        if self.fft_size < self.window_size {
+            return Err(Error::Generic(
+                "fft size must be at least the window size".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Frames of the short-time fourier transform, one full `fft_size`
+/// spectrum per frame. Frame `t` is centred on sample `t * hop`.
+pub fn stft(signal: &[f64], config: &StftConfig) -> Vec<Vec<Complex<f64>>> {
+    let window = config.window.periodic(config.window_size);
+    let factor = config.correction.factor(&window);
+    let half = config.window_size / 2;
+
+    // centre the frames and make sure the last one reaches the end
+    let frames = (half + signal.len())
+        .saturating_sub(config.window_size)
+        .div_ceil(config.hop)
+        + 1;
+    let mut padded = vec![0.; half];
+    padded.extend_from_slice(signal);
+    padded.resize((frames - 1) * config.hop + config.window_size, 0.);
+
+    let mut planner = FftPlanner::new();
+    let f = planner.plan_fft_forward(config.fft_size);
+
+    (0..frames)
+        .map(|t| {
+            let start = t * config.hop;
+            let mut frame = vec![Complex::zero(); config.fft_size];
+            for (i, w) in window.iter().enumerate() {
+                frame[i] = Complex::new(padded[start + i] * w * factor, 0.);
+            }
+            f.process(&mut frame);
+            frame
+        })
+        .collect()
+}
+
+/// Inverse of `stft` by weighted overlap-add, returning `length` samples.
+/// Each frame is windowed again and the sum divided by the summed squared
+/// windows, which gives back the signal exactly when the frames are not
+/// modified and every sample falls under a non-zero part of some window.
+pub fn istft(frames: &[Vec<Complex<f64>>], config: &StftConfig, length: usize) -> Vec<f64> {
+    let window = config.window.periodic(config.window_size);
+    let half = config.window_size / 2;
+
+    let total = frames.len().saturating_sub(1) * config.hop + config.window_size;
+    let mut out = vec![0.; total];
+    let mut weight = vec![0.; total];
+
+    let mut planner = FftPlanner::new();
+    let f = planner.plan_fft_inverse(config.fft_size);
+    let scale = config.fft_size as f64 * config.correction.factor(&window);
+
+    for (t, spectrum) in frames.iter().enumerate() {
+        let mut frame = spectrum.to_owned();
+        f.process(&mut frame);
+
+        let start = t * config.hop;
+        for (i, w) in window.iter().enumerate() {
+            out[start + i] += frame[i].re / scale * w;
+            weight[start + i] += w * w;
+        }
+    }
+
+    (0..length)
+        .map(|i| {
+            // past the last frame the weight is missing and treated as 0
+            let v = out.get(i + half).copied().unwrap_or(0.);
+            let w = weight.get(i + half).copied().unwrap_or(0.);
+            if w > 1e-10 {
+                v / w
+            } else {
+                0.
+            }
+        })
+        .collect()
+}
+
+/// Magnitude of the frames in decibels as an image, time along the x axis
+/// and frequency up the y axis from 0 at the bottom to the Nyquist limit
+/// at the top. The loudest value maps to 1 and anything `range_db` or
+/// more below it to 0. A silent signal is all 0.
+pub fn spectrogram(frames: &[Vec<Complex<f64>>], range_db: f64) -> Vec<Vec<f64>> {
+    let bins = frames[0].len() / 2 + 1;
+    let db = |v: &Complex<f64>| 20. * v.norm().max(1e-300).log10();
+
+    let max = frames
+        .iter()
+        .flat_map(|frame| frame[..bins].iter().map(db))
+        .fold(f64::NEG_INFINITY, f64::max);
+    // every bin is at the floor, nothing is louder than anything else
+    if max <= db(&Complex::zero()) {
+        return vec![vec![0.; frames.len()]; bins];
+    }
+
+    (0..bins)
+        .rev()
+        .map(|k| {
+            frames
+                .iter()
+                .map(|frame| ((db(&frame[k]) - max + range_db) / range_db).clamp(0., 1.))
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn istft_inverts_stft() {
+        let signal = (0..2000)
+            .map(|i| (i as f64 * 0.03).sin() + 0.5 * (i as f64 * 0.41).cos())
+            .collect::<Vec<f64>>();
+        let mut config = StftConfig::new(128);
+        config.window = WindowFunction::Hann;
+        config.hop = 32;
+        for correction in [Correction::None, Correction::Amplitude] {
+            config.correction = correction;
+            let back = istft(&stft(&signal, &config), &config, signal.len());
+            assert_eq!(back.len(), signal.len());
+            for (a, b) in back.iter().zip(signal.iter()) {
+                assert!((a - b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_is_black() {
+        let frames = stft(&vec![0.; 2000], &StftConfig::new(256));
+        let img = spectrogram(&frames, 80.);
+        assert_eq!(img.len(), 129);
+        assert!(img.iter().flatten().all(|v| *v == 0.));
+    }
+}
@@ -0,0 +1,224 @@
+//! Convolving a signal that arrives in pieces
+//!
+//! `StreamingConvolver` takes input in chunks of any size and returns
+//! output as soon as a whole block of it is known, keeping the overlap
+//! between blocks as state. Joining everything returned by `process` and
+//! `finish` gives the full linear convolution, the same as
+//! `conv1d::direct` on the whole signal.
+
+use crate::conv1d;
+use crate::prelude::*;
+use num::complex::Complex;
+use num::Zero;
+use rustfft::{Fft, FftPlanner};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockMethod {
+    /// convolve each block alone and add the tail into the next one
+    OverlapAdd,
+    /// convolve each block with the end of the previous one circularly
+    /// and drop the outputs that wrapped around
+    OverlapSave,
+}
+
+/// Parses `overlap-add` (or `ola`) and `overlap-save` (or `ols`).
+impl FromStr for BlockMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "overlap-add" | "ola" => Ok(BlockMethod::OverlapAdd),
+            "overlap-save" | "ols" => Ok(BlockMethod::OverlapSave),
+            _ => Err(format!(
+                "unknown block method '{}', expected overlap-add or overlap-save",
+                s
+            )),
+        }
+    }
+}
+
+pub struct StreamingConvolver {
+    method: BlockMethod,
+    kernel_len: usize,
+    fft_size: usize,
+    /// new input samples per block
+    block: usize,
+    kernel_fft: Vec<Complex<f64>>,
+    forward: Arc<dyn Fft<f64>>,
+    inverse: Arc<dyn Fft<f64>>,
+    /// input received but not yet a whole block
+    pending: Vec<f64>,
+    /// overlap-add: the last `m - 1` outputs of the previous block, still
+    /// to be added to. overlap-save: the last `m - 1` inputs.
+    overlap: Vec<f64>,
+}
+
+impl StreamingConvolver {
+    /// Set up for `kernel`. The fft size defaults to
+    /// `conv1d::block_fft_size` and is raised to a power of 2 of at least
+    /// twice the kernel length, each block then takes
+    /// `fft_size - kernel.len() + 1` samples.
+    pub fn new(kernel: &[f64], method: BlockMethod, fft_size: Option<usize>) -> Result<Self> {
+        let m = kernel.len();
+        if m == 0 {
+            return Err(Error::Kernel("the kernel is empty".to_string()));
+        }
+        let fft_size = fft_size
+            .unwrap_or_else(|| conv1d::block_fft_size(m))
+            .max(2 * m)
+            .next_power_of_two();
+
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(fft_size);
+        let inverse = planner.plan_fft_inverse(fft_size);
+
+        let mut kernel_fft = vec![Complex::zero(); fft_size];
+        for (o, v) in kernel_fft.iter_mut().zip(kernel.iter()) {
+            *o = Complex::new(*v, 0.);
+        }
+        forward.process(&mut kernel_fft);
+
+        Ok(StreamingConvolver {
+            method,
+            kernel_len: m,
+            fft_size,
+            block: fft_size - m + 1,
+            kernel_fft,
+            forward,
+            inverse,
+            pending: vec![],
+            overlap: vec![0.; m - 1],
+        })
+    }
+
+    /// new input samples needed before `process` returns more output
+    pub fn block_size(&self) -> usize {
+        self.block
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Add a chunk of input, returning the output for every whole block
+    /// received so far. The output trails the input by less than one
+    /// block.
+    pub fn process(&mut self, chunk: &[f64]) -> Vec<f64> {
+        self.pending.extend_from_slice(chunk);
+
+        let blocks = self.pending.len() / self.block;
+        let mut out = Vec::with_capacity(blocks * self.block);
+        for b in 0..blocks {
+            let start = b * self.block;
+            let block = self.pending[start..start + self.block].to_vec();
+            out.extend(self.process_block(&block));
+        }
+        self.pending.drain(..blocks * self.block);
+
+        out
+    }
+
+    /// Flush the remaining input and the tail of the kernel, `m - 1`
+    /// samples past the end of the input, and reset for a new signal.
+    pub fn finish(&mut self) -> Vec<f64> {
+        let zeros = vec![0.; self.kernel_len - 1];
+        let mut out = self.process(&zeros);
+
+        // the last partial block, padded with zeros
+        let remaining = self.pending.len();
+        if remaining > 0 {
+            let mut block = std::mem::take(&mut self.pending);
+            block.resize(self.block, 0.);
+            out.extend(self.process_block(&block).into_iter().take(remaining));
+        }
+
+        self.reset();
+        out
+    }
+
+    /// forget all input, ready for a new signal
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.overlap = vec![0.; self.kernel_len - 1];
+    }
+
+    /// convolve one block of exactly `self.block` samples
+    fn process_block(&mut self, block: &[f64]) -> Vec<f64> {
+        let m = self.kernel_len;
+        let scale = self.fft_size as f64;
+
+        let mut x = vec![Complex::zero(); self.fft_size];
+        match self.method {
+            BlockMethod::OverlapAdd => {
+                for (o, v) in x.iter_mut().zip(block.iter()) {
+                    *o = Complex::new(*v, 0.);
+                }
+            }
+            BlockMethod::OverlapSave => {
+                for (o, v) in x.iter_mut().zip(self.overlap.iter().chain(block.iter())) {
+                    *o = Complex::new(*v, 0.);
+                }
+            }
+        }
+
+        self.forward.process(&mut x);
+        for (a, h) in x.iter_mut().zip(self.kernel_fft.iter()) {
+            *a *= h;
+        }
+        self.inverse.process(&mut x);
+        let y = x.iter().map(|v| v.re / scale).collect::<Vec<f64>>();
+
+        match self.method {
+            BlockMethod::OverlapAdd => {
+                // the block is at least as long as the tail
+                let mut out = y[..self.block].to_vec();
+                for (o, t) in out.iter_mut().zip(self.overlap.iter()) {
+                    *o += t;
+                }
+                self.overlap = y[self.block..self.block + m - 1].to_vec();
+                out
+            }
+            BlockMethod::OverlapSave => {
+                self.overlap = block[self.block - (m - 1)..].to_vec();
+                y[m - 1..].to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_join_to_the_full_convolution() {
+        let signal = (0..500)
+            .map(|i| ((i * 37) % 23) as f64 - 11.)
+            .collect::<Vec<f64>>();
+        let kernel = (0..21).map(|i| 1. / (1 + i) as f64).collect::<Vec<f64>>();
+        let expected = conv1d::direct(&signal, &kernel);
+
+        for method in [BlockMethod::OverlapAdd, BlockMethod::OverlapSave] {
+            let mut convolver = StreamingConvolver::new(&kernel, method, Some(64)).unwrap();
+            let mut out = vec![];
+            let mut rest = &signal[..];
+            // odd sizes, shorter and longer than a block
+            for size in [1, 7, 3, 131, 45, 9].iter().cycle() {
+                if rest.is_empty() {
+                    break;
+                }
+                let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+                out.extend(convolver.process(chunk));
+                rest = tail;
+            }
+            out.extend(convolver.finish());
+
+            assert_eq!(out.len(), expected.len());
+            for (a, b) in out.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-9, "{:?}: {} != {}", method, a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! Convolution, padding and pooling of N-dimensional volumes
+//!
+//! A `Volume` keeps its values in row-major order with the last axis
+//! changing fastest, so a stack of image slices has the shape
+//! `[slices, rows, cols]`. The functions here mirror the 2d ones in
+//! `padding`, `convolve` and `pooling` for any number of axes. Kernels
+//! are volumes too, anchored at their centre `shape[d] / 2` on each axis.
+
+use crate::padding::{self, Padding};
+use crate::pooling::PoolMethod;
+use crate::prelude::*;
+use num::complex::Complex;
+use num::Zero;
+use rand::Rng;
+use rayon::prelude::*;
+use rustfft::FftPlanner;
+use std::str::FromStr;
+
+/// A non-empty N-dimensional array of values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    shape: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl Volume {
+    /// Wrap `values` in row-major order, checking they fill `shape`.
+    pub fn new(shape: Vec<usize>, values: Vec<f64>) -> Result<Self> {
+        if shape.is_empty() || shape.contains(&0) {
+            return Err(Error::Generic(format!(
+                "volume shape {:?} must have at least one axis and no empty axes",
+                shape
+            )));
+        }
+        let len = shape.iter().product::<usize>();
+        if values.len() != len {
+            return Err(Error::Generic(format!(
+                "{} values do not fill a volume of shape {:?}",
+                values.len(),
+                shape
+            )));
+        }
+        Ok(Volume { shape, values })
+    }
+
+    /// a volume of `shape` with every value set to `value`, the shape is
+    /// not checked so it must come from a valid volume
+    fn filled(shape: Vec<usize>, value: f64) -> Self {
+        let len = shape.iter().product::<usize>();
+        Volume {
+            shape,
+            values: vec![value; len],
+        }
+    }
+
+    /// Stack image slices of equal size into a `[slices, rows, cols]`
+    /// volume.
+    pub fn from_slices(slices: &[Vec<Vec<f64>>]) -> Result<Self> {
+        if slices.is_empty() || slices[0].is_empty() {
+            return Err(Error::Generic("there are no slices to stack".to_string()));
+        }
+        let (rows, cols) = (slices[0].len(), slices[0][0].len());
+        for (n, slice) in slices.iter().enumerate() {
+            if slice.len() != rows || slice.iter().any(|row| row.len() != cols) {
+                return Err(Error::Generic(format!(
+                    "slice {} is not {}x{} like the first slice",
+                    n, cols, rows
+                )));
+            }
+        }
+
+        let values = slices.iter().flatten().flatten().copied().collect();
+        Volume::new(vec![slices.len(), rows, cols], values)
+    }
+
+    /// Split a 3d volume back into its slices.
+    pub fn to_slices(&self) -> Result<Vec<Vec<Vec<f64>>>> {
+        if self.ndim() != 3 {
+            return Err(Error::Generic(format!(
+                "only 3d volumes split into slices, not {}d",
+                self.ndim()
+            )));
+        }
+        let cols = self.shape[2];
+        Ok(self
+            .values
+            .chunks(self.shape[1] * cols)
+            .map(|slice| slice.chunks(cols).map(|row| row.to_vec()).collect())
+            .collect())
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// number of axes
+    pub fn ndim(&self) -> usize {
+        self.shape.len()
+    }
+
+    /// number of values
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<f64> {
+        self.values
+    }
+
+    /// value at `index`, one coordinate per axis
+    pub fn get(&self, index: &[usize]) -> f64 {
+        self.values[self.offset(index)]
+    }
+
+    pub fn set(&mut self, index: &[usize], value: f64) {
+        let offset = self.offset(index);
+        self.values[offset] = value;
+    }
+
+    /// distance in `values` between neighbours along each axis
+    pub fn strides(&self) -> Vec<usize> {
+        strides_of(&self.shape)
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.values.iter().sum()
+    }
+
+    /// Scale so the values sum to 1, leaving zero-sum volumes unchanged.
+    pub fn normalize(&self) -> Self {
+        let sum = self.sum();
+        if sum == 0. {
+            return self.clone();
+        }
+        Volume {
+            shape: self.shape.clone(),
+            values: self.values.iter().map(|v| v / sum).collect(),
+        }
+    }
+
+    /// reverse every axis, the N-dimensional 180 degree rotation
+    pub fn flip(&self) -> Self {
+        Volume {
+            shape: self.shape.clone(),
+            values: self.values.iter().rev().copied().collect(),
+        }
+    }
+
+    /// position of `index` in `values`
+    fn offset(&self, index: &[usize]) -> usize {
+        index.iter().zip(self.strides()).map(|(i, s)| i * s).sum()
+    }
+}
+
+/// A kernel built for a volume with any number of axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VolumeKernel {
+    /// normalized gaussian of `size` along every axis
+    Gaussian { size: usize, sigma: f64 },
+    /// normalized box of `size` along every axis
+    Box(usize),
+}
+
+/// Parses `gaussian:<size>:<sigma>` and `box:<size>`.
+impl FromStr for VolumeKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<&str>>();
+        let size = |v: &str| {
+            v.parse::<usize>()
+                .map_err(|_| format!("'{}' is not a whole number", v))
+        };
+
+        match parts.as_slice() {
+            ["gaussian", n, sigma] => Ok(VolumeKernel::Gaussian {
+                size: size(n)?,
+                sigma: sigma
+                    .parse::<f64>()
+                    .map_err(|_| format!("'{}' is not a number", sigma))?,
+            }),
+            ["box", n] => Ok(VolumeKernel::Box(size(n)?)),
+            _ => Err(format!(
+                "unknown volume kernel '{}', expected gaussian:<size>:<sigma> or box:<size>",
+                s
+            )),
+        }
+    }
+}
+
+impl VolumeKernel {
+    /// the kernel with `ndim` axes
+    pub fn build(&self, ndim: usize) -> Result<Volume> {
+        match *self {
+            VolumeKernel::Gaussian { size, sigma } => gaussian(ndim, size, sigma),
+            VolumeKernel::Box(size) => box_blur(ndim, size),
+        }
+    }
+
+    /// label used in output names
+    pub fn label(&self) -> String {
+        match *self {
+            VolumeKernel::Gaussian { size, sigma } => format!("gaussian-{}-{}", size, sigma),
+            VolumeKernel::Box(size) => format!("box-{}", size),
+        }
+    }
+}
+
+/// normalized gaussian with `size` values along each of `ndim` axes
+pub fn gaussian(ndim: usize, size: usize, sigma: f64) -> Result<Volume> {
+    if !(sigma > 0. && sigma.is_finite()) {
+        return Err(Error::Kernel("sigma must be positive".to_string()));
+    }
+    let mut kernel = box_blur(ndim, size)?;
+    let center = (size / 2) as f64;
+    let variance = sigma.powi(2);
+
+    for (f, v) in kernel.values.iter_mut().enumerate() {
+        let r2 = unravel(f, &vec![size; ndim])
+            .iter()
+            .map(|i| (*i as f64 - center).powi(2))
+            .sum::<f64>();
+        *v = (-r2 / (2. * variance)).exp();
+    }
+    Ok(kernel.normalize())
+}
+
+/// `size` values along each of `ndim` axes, all equal and summing to 1
+pub fn box_blur(ndim: usize, size: usize) -> Result<Volume> {
+    if ndim == 0 || size == 0 {
+        return Err(Error::Kernel(
+            "volume kernels need at least one axis of at least 1 value".to_string(),
+        ));
+    }
+    let len = size.pow(ndim as u32);
+    Volume::new(vec![size; ndim], vec![1. / len as f64; len])
+}
+
+/// Pad by `size - 1` along every axis with `mode`, placing the input at
+/// `size / 2` like `padding::zero_pad`, so a window of `size` starting at
+/// each input position is centred on it.
+pub fn pad(input: &Volume, size: usize, mode: Padding) -> Result<Volume> {
+    check_window(size)?;
+    let before = vec![size / 2; input.ndim()];
+    let after = vec![(size - 1) - size / 2; input.ndim()];
+    Ok(pad_sides(input, &before, &after, mode))
+}
+
+/// Pad by an explicit amount before and after the input on each axis,
+/// like `padding::pad_sides`. Reflection mirrors about the edge value
+/// without repeating it.
+pub fn pad_sides(input: &Volume, before: &[usize], after: &[usize], mode: Padding) -> Volume {
+    let shape = input
+        .shape
+        .iter()
+        .zip(before.iter().zip(after.iter()))
+        .map(|(n, (b, a))| n + b + a)
+        .collect::<Vec<usize>>();
+    let mut out = Volume::filled(shape, 0.);
+
+    let shape = out.shape.clone();
+    for (f, v) in out.values.iter_mut().enumerate() {
+        let index = unravel(f, &shape);
+        let mut source = Vec::with_capacity(index.len());
+        let mut inside = true;
+        for d in 0..index.len() {
+            let i = index[d] as i64 - before[d] as i64;
+            let n = input.shape[d];
+            inside &= i >= 0 && i < n as i64;
+            source.push(padding::reflect(i, n));
+        }
+
+        *v = match mode {
+            _ if inside => input.get(&source),
+            Padding::Zero => 0.,
+            Padding::Constant(value) => value,
+            Padding::Reflection => input.get(&source),
+        };
+    }
+
+    out
+}
+
+/// Cross-correlation of a volume with a kernel of as many axes, without
+/// flipping it. The input is padded with `mode`, and the output is the
+/// same shape as the input, like `convolve::correlate_2d`.
+pub fn correlate(input: &Volume, kernel: &Volume, mode: Padding) -> Result<Volume> {
+    check_axes(input, kernel)?;
+    let anchor = kernel.shape.iter().map(|m| m / 2).collect::<Vec<usize>>();
+    Ok(correlate_anchored(input, kernel, &anchor, mode))
+}
+
+/// True convolution of a volume with a kernel of as many axes, the
+/// correlation with the kernel reversed along every axis, like
+/// `convolve::convolve_2d`.
+pub fn convolve(input: &Volume, kernel: &Volume, mode: Padding) -> Result<Volume> {
+    check_axes(input, kernel)?;
+    Ok(correlate_anchored(
+        input,
+        &kernel.flip(),
+        &flipped_anchor(kernel),
+        mode,
+    ))
+}
+
+/// Same result as `correlate`, computed with an N-dimensional fft of
+/// the padded input at its exact size, like `convolve::fft_correlate_2d`.
+pub fn fft_correlate(input: &Volume, kernel: &Volume, mode: Padding) -> Result<Volume> {
+    check_axes(input, kernel)?;
+    let anchor = kernel.shape.iter().map(|m| m / 2).collect::<Vec<usize>>();
+    Ok(fft_correlate_anchored(input, kernel, &anchor, mode))
+}
+
+/// Same result as `convolve`, computed with the fft.
+pub fn fft_convolve(input: &Volume, kernel: &Volume, mode: Padding) -> Result<Volume> {
+    check_axes(input, kernel)?;
+    Ok(fft_correlate_anchored(
+        input,
+        &kernel.flip(),
+        &flipped_anchor(kernel),
+        mode,
+    ))
+}
+
+/// Pool with a stride of 1, so the output is the same shape as the
+/// input. Each output value is `method` applied to the cube of `size`
+/// along every axis centred on it, with the input padded by `mode`.
+pub fn pool(input: &Volume, size: usize, mode: Padding, method: PoolMethod) -> Result<Volume> {
+    match method {
+        PoolMethod::Max => pool_with(input, size, mode, |w: &[f64]| {
+            w.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        }),
+        PoolMethod::Min => pool_with(input, size, mode, |w: &[f64]| {
+            w.iter().copied().fold(f64::INFINITY, f64::min)
+        }),
+        PoolMethod::Avg => pool_with(input, size, mode, |w: &[f64]| {
+            w.iter().sum::<f64>() / w.len() as f64
+        }),
+        PoolMethod::L2 => pool_with(input, size, mode, |w: &[f64]| {
+            w.iter().map(|v| v * v).sum::<f64>().sqrt()
+        }),
+        PoolMethod::Stochastic => pool_with(input, size, mode, |w: &[f64]| {
+            w[rand::thread_rng().gen_range(0..w.len())]
+        }),
+    }
+}
+
+/// Pool with any reduction of the window values, which are passed in
+/// row-major order. The windows are reduced in parallel.
+pub fn pool_with<F>(input: &Volume, size: usize, mode: Padding, reducer: F) -> Result<Volume>
+where
+    F: Fn(&[f64]) -> f64 + Sync,
+{
+    let padded = pad(input, size, mode)?;
+    let offsets = window_offsets(&vec![size; input.ndim()], &padded.strides());
+
+    let mut out = Volume::filled(input.shape.clone(), 0.);
+    out.values.par_iter_mut().enumerate().for_each_init(
+        || Vec::with_capacity(offsets.len()),
+        |window, (f, v)| {
+            let base = padded_base(f, &input.shape, &padded.strides());
+            window.clear();
+            window.extend(offsets.iter().map(|o| padded.values[base + o]));
+            *v = reducer(window);
+        },
+    );
+
+    Ok(out)
+}
+
+/// correlation with kernel index `anchor` placed over each output value
+fn correlate_anchored(input: &Volume, kernel: &Volume, anchor: &[usize], mode: Padding) -> Volume {
+    let padded = pad_for_kernel(input, kernel, anchor, mode);
+    let strides = padded.strides();
+
+    // each kernel value with its offset in the padded volume
+    let taps = window_offsets(&kernel.shape, &strides)
+        .into_iter()
+        .zip(kernel.values.iter().copied())
+        .filter(|(_, k)| *k != 0.)
+        .collect::<Vec<(usize, f64)>>();
+
+    let mut out = Volume::filled(input.shape.clone(), 0.);
+    out.values.par_iter_mut().enumerate().for_each(|(f, v)| {
+        let base = padded_base(f, &input.shape, &strides);
+        *v = taps.iter().map(|(o, k)| padded.values[base + o] * k).sum();
+    });
+
+    out
+}
+
+fn fft_correlate_anchored(
+    input: &Volume,
+    kernel: &Volume,
+    anchor: &[usize],
+    mode: Padding,
+) -> Volume {
+    let padded = pad_for_kernel(input, kernel, anchor, mode);
+    let shape = padded.shape.clone();
+
+    // correlating is multiplying by the spectrum of the flipped kernel
+    let flipped = kernel.flip();
+    let strides = padded.strides();
+    let mut kernel_fft = vec![Complex::zero(); padded.len()];
+    for (o, k) in window_offsets(&kernel.shape, &strides)
+        .iter()
+        .zip(flipped.values.iter())
+    {
+        kernel_fft[*o] = Complex::new(*k, 0.);
+    }
+
+    let mut image_fft = padded
+        .values
+        .iter()
+        .map(|v| Complex::new(*v, 0.))
+        .collect::<Vec<Complex<f64>>>();
+
+    fft_nd(&mut image_fft, &shape, false);
+    fft_nd(&mut kernel_fft, &shape, false);
+    for (a, b) in image_fft.iter_mut().zip(kernel_fft.iter()) {
+        *a *= b;
+    }
+    fft_nd(&mut image_fft, &shape, true);
+
+    // the linear result starts one kernel size in along each axis
+    let scale = padded.len() as f64;
+    let start = kernel
+        .shape
+        .iter()
+        .zip(strides.iter())
+        .map(|(m, s)| (m - 1) * s)
+        .sum::<usize>();
+    let mut out = Volume::filled(input.shape.clone(), 0.);
+    for (f, v) in out.values.iter_mut().enumerate() {
+        *v = image_fft[padded_base(f, &input.shape, &strides) + start].re / scale;
+    }
+
+    out
+}
+
+/// Transform along every axis in turn. The inverse is not scaled.
+fn fft_nd(values: &mut [Complex<f64>], shape: &[usize], inverse: bool) {
+    let mut planner = FftPlanner::new();
+    let strides = strides_of(shape);
+
+    for (axis, &n) in shape.iter().enumerate() {
+        let fft = if inverse {
+            planner.plan_fft_inverse(n)
+        } else {
+            planner.plan_fft_forward(n)
+        };
+        let stride = strides[axis];
+        let mut line = vec![Complex::zero(); n];
+
+        // each line along the axis starts where its coordinate is 0
+        for start in (0..values.len()).filter(|f| (f / stride).is_multiple_of(n)) {
+            for i in 0..n {
+                line[i] = values[start + i * stride];
+            }
+            fft.process(&mut line);
+            for i in 0..n {
+                values[start + i * stride] = line[i];
+            }
+        }
+    }
+}
+
+/// pad the input so the kernel anchor can sit on every input value
+fn pad_for_kernel(input: &Volume, kernel: &Volume, anchor: &[usize], mode: Padding) -> Volume {
+    let after = kernel
+        .shape
+        .iter()
+        .zip(anchor.iter())
+        .map(|(m, a)| m - 1 - a)
+        .collect::<Vec<usize>>();
+    pad_sides(input, anchor, &after, mode)
+}
+
+/// where the centre anchor ends up once the kernel is flipped
+fn flipped_anchor(kernel: &Volume) -> Vec<usize> {
+    kernel.shape.iter().map(|m| m - 1 - m / 2).collect()
+}
+
+/// windows need at least one value along each axis
+fn check_window(size: usize) -> Result<()> {
+    if size == 0 {
+        return Err(Error::Generic("window size must be at least 1".to_string()));
+    }
+    Ok(())
+}
+
+fn check_axes(input: &Volume, kernel: &Volume) -> Result<()> {
+    if input.ndim() != kernel.ndim() {
+        return Err(Error::Kernel(format!(
+            "a {}d kernel cannot filter a {}d volume",
+            kernel.ndim(),
+            input.ndim()
+        )));
+    }
+    Ok(())
+}
+
+/// offsets in a volume with `strides` of every index in a window of `shape`
+fn window_offsets(shape: &[usize], strides: &[usize]) -> Vec<usize> {
+    let len = shape.iter().product::<usize>();
+    (0..len)
+        .map(|f| {
+            unravel(f, shape)
+                .iter()
+                .zip(strides.iter())
+                .map(|(i, s)| i * s)
+                .sum()
+        })
+        .collect()
+}
+
+/// position in the padded volume with `strides` of input value `f`
+fn padded_base(f: usize, shape: &[usize], strides: &[usize]) -> usize {
+    unravel(f, shape)
+        .iter()
+        .zip(strides.iter())
+        .map(|(i, s)| i * s)
+        .sum()
+}
+
+fn strides_of(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for d in (0..shape.len().saturating_sub(1)).rev() {
+        strides[d] = strides[d + 1] * shape[d + 1];
+    }
+    strides
+}
+
+/// the index on each axis of position `f` in row-major order
+fn unravel(mut f: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for d in (0..shape.len()).rev() {
+        index[d] = f % shape[d];
+        f /= shape[d];
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_reject_size_zero() {
+        let input = Volume::new(vec![2, 2, 2], vec![1.; 8]).unwrap();
+        assert!(pad(&input, 0, Padding::Zero).is_err());
+        assert!(pool(&input, 0, Padding::Zero, PoolMethod::Max).is_err());
+        assert!(pool_with(&input, 0, Padding::Zero, |w: &[f64]| w[0]).is_err());
+
+        let pooled = pool(&input, 1, Padding::Zero, PoolMethod::Max).unwrap();
+        assert_eq!(pooled.values(), input.values());
+    }
+
+    #[test]
+    fn gaussian_rejects_bad_sigmas() {
+        for sigma in [0., -1., f64::NAN, f64::INFINITY] {
+            assert!(gaussian(3, 5, sigma).is_err(), "{}", sigma);
+        }
+    }
+
+    /// a volume of `shape` holding a fixed jumble of values
+    fn volume(shape: &[usize], seed: usize) -> Volume {
+        let len = shape.iter().product::<usize>();
+        let values = (0..len)
+            .map(|i| ((i * 7 + seed) % 13) as f64 - 6.)
+            .collect();
+        Volume::new(shape.to_vec(), values).unwrap()
+    }
+
+    #[test]
+    fn fft_matches_direct() {
+        // odd and even kernel sides, so both anchors are exercised
+        let cases = [
+            (vec![17], vec![4]),
+            (vec![6, 9], vec![3, 2]),
+            (vec![5, 6, 7], vec![3, 2, 4]),
+        ];
+        for (shape, kernel_shape) in cases {
+            let input = volume(&shape, 1);
+            let kernel = volume(&kernel_shape, 5);
+            for mode in [Padding::Zero, Padding::Reflection, Padding::Constant(2.)] {
+                let pairs = [
+                    (
+                        correlate(&input, &kernel, mode).unwrap(),
+                        fft_correlate(&input, &kernel, mode).unwrap(),
+                    ),
+                    (
+                        convolve(&input, &kernel, mode).unwrap(),
+                        fft_convolve(&input, &kernel, mode).unwrap(),
+                    ),
+                ];
+                for (direct, fft) in pairs {
+                    assert_eq!(direct.shape(), input.shape());
+                    assert_eq!(fft.shape(), input.shape());
+                    for (a, b) in direct.values().iter().zip(fft.values()) {
+                        assert!(
+                            (a - b).abs() < 1e-9,
+                            "{:?} {:?}: {} != {}",
+                            shape,
+                            mode,
+                            a,
+                            b
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Window functions for the fft
+//!
+//! The fft treats a signal as one period of a repeating one, so a jump
+//! between its ends spreads energy across the whole spectrum (leakage).
+//! Tapering the ends towards zero with a window before transforming
+//! reduces the leakage at the cost of wider peaks.
+//!
+//! Windowing also scales the spectrum down. Multiply by
+//! `amplitude_correction` to read the height of a sinusoid correctly, or
+//! by `energy_correction` to keep the total power of noise. `Correction`
+//! picks one of them for the spectrum command and `stft`.
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// largest kaiser beta, `bessel_i0` overflows a little past 700
+const MAX_KAISER_BETA: f64 = 700.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunction {
+    /// all ones, the same as not windowing
+    Rectangular,
+    /// raised cosine reaching zero at both ends
+    Hann,
+    /// raised cosine with a small step at the ends, lower first sidelobe
+    Hamming,
+    /// three cosine terms, low sidelobes and a wide main lobe
+    Blackman,
+    /// Kaiser-Bessel, `beta` trades main lobe width (small) for sidelobe
+    /// level (large)
+    Kaiser(f64),
+    /// flat in the middle with cosine tapers over a fraction `alpha` of
+    /// the length, 0 is rectangular and 1 is Hann
+    Tukey(f64),
+}
+
+/// Parses `rectangular`, `hann`, `hamming`, `blackman`, `kaiser[:<beta>]`
+/// and `tukey[:<alpha>]`, with beta defaulting to 8.6 and alpha to 0.5.
+impl FromStr for WindowFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("'{}' is not a number", v))
+        };
+
+        match s.split(':').collect::<Vec<&str>>().as_slice() {
+            ["rectangular"] | ["none"] => Ok(WindowFunction::Rectangular),
+            ["hann"] => Ok(WindowFunction::Hann),
+            ["hamming"] => Ok(WindowFunction::Hamming),
+            ["blackman"] => Ok(WindowFunction::Blackman),
+            ["kaiser"] => Ok(WindowFunction::Kaiser(8.6)),
+            ["kaiser", beta] => {
+                let beta = number(beta)?;
+                if !(0. ..=MAX_KAISER_BETA).contains(&beta) {
+                    return Err(format!("kaiser beta must be 0-{}", MAX_KAISER_BETA));
+                }
+                Ok(WindowFunction::Kaiser(beta))
+            }
+            ["tukey"] => Ok(WindowFunction::Tukey(0.5)),
+            ["tukey", alpha] => {
+                let alpha = number(alpha)?;
+                if !(0. ..=1.).contains(&alpha) {
+                    return Err("tukey alpha must be 0-1".to_string());
+                }
+                Ok(WindowFunction::Tukey(alpha))
+            }
+            _ => Err(format!(
+                "unknown window '{}', expected rectangular, hann, hamming, blackman, kaiser:<beta> or tukey:<alpha>",
+                s
+            )),
+        }
+    }
+}
+
+/// Which factor undoes the scaling of a window, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correction {
+    None,
+    /// `amplitude_correction`, for the heights of peaks
+    Amplitude,
+    /// `energy_correction`, for the power of noise
+    Energy,
+}
+
+/// Parses `none`, `amplitude` and `energy`.
+impl FromStr for Correction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Correction::None),
+            "amplitude" => Ok(Correction::Amplitude),
+            "energy" => Ok(Correction::Energy),
+            _ => Err(format!(
+                "unknown correction '{}', expected none, amplitude or energy",
+                s
+            )),
+        }
+    }
+}
+
+impl Correction {
+    /// the factor for `window`, 1 for `None`
+    pub fn factor(&self, window: &[f64]) -> f64 {
+        match self {
+            Correction::None => 1.,
+            Correction::Amplitude => amplitude_correction(window),
+            Correction::Energy => energy_correction(window),
+        }
+    }
+}
+
+impl WindowFunction {
+    /// Symmetric window of length `n`, the first and last values are
+    /// equal. Use this for filter design.
+    pub fn symmetric(&self, n: usize) -> Vec<f64> {
+        // too short to have two ends
+        if n <= 1 {
+            return vec![1.; n];
+        }
+        (0..n).map(|i| self.at(i as f64 / (n - 1) as f64)).collect()
+    }
+
+    /// Periodic window of length `n`, one period of a window of length
+    /// `n + 1` without its last value. Use this before an fft.
+    pub fn periodic(&self, n: usize) -> Vec<f64> {
+        // a single value would sit at the zero of most windows
+        if n <= 1 {
+            return vec![1.; n];
+        }
+        (0..n).map(|i| self.at(i as f64 / n as f64)).collect()
+    }
+
+    /// value at position `x` from 0 (first sample) to 1 (last)
+    fn at(&self, x: f64) -> f64 {
+        let c = |k: f64| (2. * PI * k * x).cos();
+        match self {
+            WindowFunction::Rectangular => 1.,
+            WindowFunction::Hann => 0.5 - 0.5 * c(1.),
+            WindowFunction::Hamming => 0.54 - 0.46 * c(1.),
+            WindowFunction::Blackman => 0.42 - 0.5 * c(1.) + 0.08 * c(2.),
+            WindowFunction::Kaiser(beta) => {
+                let t = 2. * x - 1.;
+                bessel_i0(beta * (1. - t * t).max(0.).sqrt()) / bessel_i0(*beta)
+            }
+            WindowFunction::Tukey(alpha) => {
+                // distance from the nearer end, the window is symmetric
+                let d = x.min(1. - x);
+                if *alpha == 0. || d >= alpha / 2. {
+                    1.
+                } else {
+                    0.5 - 0.5 * (2. * PI * d / alpha).cos()
+                }
+            }
+        }
+    }
+
+    /// Separable 2d window, the outer product of periodic windows along
+    /// the rows and the columns.
+    pub fn window_2d(&self, rows: usize, cols: usize) -> Vec<Vec<f64>> {
+        let (wy, wx) = (self.periodic(rows), self.periodic(cols));
+        wy.iter()
+            .map(|y| wx.iter().map(|x| y * x).collect())
+            .collect()
+    }
+
+    /// multiply a signal by the periodic window of its length
+    pub fn apply(&self, signal: &[f64]) -> Vec<f64> {
+        signal
+            .iter()
+            .zip(self.periodic(signal.len()))
+            .map(|(v, w)| v * w)
+            .collect()
+    }
+
+    /// multiply an image by the 2d window of its size
+    pub fn apply_2d(&self, input: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        self.apply_2d_corrected(input, Correction::None)
+    }
+
+    /// `apply_2d` scaled by the `correction` factor of the 2d window
+    pub fn apply_2d_corrected(&self, input: &[Vec<f64>], correction: Correction) -> Vec<Vec<f64>> {
+        let window = self.window_2d(input.len(), input[0].len());
+        let factor = correction.factor(&window.concat());
+        input
+            .iter()
+            .zip(window.iter())
+            .map(|(row, w_row)| {
+                row.iter()
+                    .zip(w_row.iter())
+                    .map(|(v, w)| v * w * factor)
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// `n / sum(w)`, restores the amplitude of a sinusoid in the spectrum of
+/// a windowed signal
+pub fn amplitude_correction(window: &[f64]) -> f64 {
+    window.len() as f64 / window.iter().sum::<f64>()
+}
+
+/// `sqrt(n / sum(w^2))`, restores the power of broadband noise in the
+/// spectrum of a windowed signal
+pub fn energy_correction(window: &[f64]) -> f64 {
+    (window.len() as f64 / window.iter().map(|w| w * w).sum::<f64>()).sqrt()
+}
+
+/// modified Bessel function of the first kind, order 0, from its power
+/// series
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let mut k = 1.;
+    while term > sum * 1e-16 {
+        term *= (x / (2. * k)).powi(2);
+        sum += term;
+        k += 1.;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_windows_are_empty() {
+        for window in [WindowFunction::Hann, WindowFunction::Kaiser(8.6)] {
+            assert!(window.symmetric(0).is_empty());
+            assert!(window.periodic(0).is_empty());
+            assert_eq!(window.symmetric(1), vec![1.]);
+        }
+        for window in [
+            WindowFunction::Hann,
+            WindowFunction::Blackman,
+            WindowFunction::Tukey(0.5),
+        ] {
+            let w = window.periodic(1);
+            assert_eq!(w, vec![1.]);
+            assert_eq!(Correction::Amplitude.factor(&w), 1.);
+            assert!(window
+                .window_2d(1, 8)
+                .iter()
+                .flatten()
+                .all(|v| v.is_finite()));
+        }
+    }
+
+    #[test]
+    fn kaiser_beta_is_bounded() {
+        assert!("kaiser:2000".parse::<WindowFunction>().is_err());
+        assert!("kaiser:NaN".parse::<WindowFunction>().is_err());
+        assert!("kaiser:-1".parse::<WindowFunction>().is_err());
+        let w = "kaiser:700".parse::<WindowFunction>().unwrap().periodic(64);
+        assert!(w.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn corrections_undo_the_window() {
+        let window = WindowFunction::Hann.periodic(64);
+        let amplitude = Correction::Amplitude.factor(&window);
+        let energy = Correction::Energy.factor(&window);
+        // the mean of a periodic hann is 1/2 and its mean square 3/8
+        assert!((amplitude - 2.).abs() < 1e-12);
+        assert!((energy - (8f64 / 3.).sqrt()).abs() < 1e-12);
+
+        let flat = WindowFunction::Rectangular.periodic(16);
+        assert_eq!(Correction::Amplitude.factor(&flat), 1.);
+        assert_eq!(Correction::None.factor(&window), 1.);
+    }
+}